@@ -0,0 +1,152 @@
+use clap::{Args, Subcommand};
+use multidimension::{Size, View, Array};
+use fvq::io::{cli, load_image, ImageInfo};
+use fvq::codec::{Header, decode_with_metadata};
+use fvq::transform::{Haar, from_haar, twiddle_grid};
+use fvq::quantize::{quantize_tree, LumaModel, Mode};
+use fvq::{Grid, Tree, Position, Pyramid};
+
+/// The experiments, each of which processes an image file.
+#[derive(Debug, Subcommand)]
+pub enum Experiment {
+    /// Remove all the wavelets, leaving only the low-frequency image.
+    Blur(cli::InOutOrder),
+
+    /// Apply the wavelet transform without smoothing.
+    Box(cli::InOutOrder),
+
+    /// Draw a montage of the wavelet pyramid.
+    Wavelet(cli::InOutOrder),
+
+    /// Enlarge the image by a factor of two `order` times.
+    Enlarge(cli::InOutOrder),
+
+    /// Compress and decompress the image.
+    Quantize(Quantize),
+
+    /// Compare the quantisation error of the BCC product lattice with that of
+    /// the family-wise glued lattice.
+    Glue(Glue),
+}
+
+#[derive(Debug, Args)]
+pub struct Quantize {
+    #[command(flatten)]
+    io: cli::InOutOrder,
+
+    #[command(flatten)]
+    codec: cli::Codec,
+}
+
+#[derive(Debug, Args)]
+pub struct Glue {
+    /// The images to measure.
+    image_paths: Vec<String>,
+
+    /// The order of the wavelet pyramid.
+    #[arg(short = 'n', long, default_value_t = 5)]
+    order: usize,
+}
+
+impl Experiment {
+    pub fn run(&self) -> fvq::Result {
+        match self {
+            Experiment::Blur(args) => blur(args),
+            Experiment::Box(args) => box_(args),
+            Experiment::Wavelet(args) => wavelet(args),
+            Experiment::Enlarge(args) => enlarge(args),
+            Experiment::Quantize(args) => quantize(args),
+            Experiment::Glue(args) => glue(args),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+fn blur(args: &cli::InOutOrder) -> fvq::Result {
+    let order = args.order(5);
+    let (in_pixels, in_info) = args.load()?;
+    let out_pixels = in_pixels.pad_to_multiple(1 << order).map_channels(|pixels| {
+        let mut pyramid = Pyramid::from_pixels(order, true, pixels);
+        pyramid.size().each(|yx| {
+            let pos = Position {level: 0, yx};
+            pyramid.set(pos, &Tree::Leaf);
+        });
+        pyramid.to_pixels(true)
+    }).crop(in_pixels.size());
+    let depth = args.depth(in_info.depth);
+    args.save("blur", &out_pixels, &ImageInfo {depth, ..in_info})
+}
+
+fn box_(args: &cli::InOutOrder) -> fvq::Result {
+    let order = args.order(5);
+    let (in_pixels, in_info) = args.load()?;
+    let out_pixels = in_pixels.pad_to_multiple(1 << order).map_channels(|pixels| {
+        let pyramid = Pyramid::from_pixels(order, true, pixels);
+        pyramid.to_pixels(false)
+    }).crop(in_pixels.size());
+    let depth = args.depth(in_info.depth);
+    args.save("box", &out_pixels, &ImageInfo {depth, ..in_info})
+}
+
+fn wavelet(args: &cli::InOutOrder) -> fvq::Result {
+    let order = args.order(5);
+    let (in_pixels, in_info) = args.load()?;
+    let in_pixels = in_pixels.pad_to_multiple(1 << order);
+    let out_pixels = in_pixels.map_channels(|pixels| {
+        let pyramid = Pyramid::from_pixels(order, true, pixels);
+        pyramid.montage()
+    });
+    let depth = args.depth(in_info.depth);
+    args.save("wavelet", &out_pixels, &ImageInfo {depth, ..in_info})
+}
+
+fn enlarge(args: &cli::InOutOrder) -> fvq::Result {
+    let (in_pixels, in_info) = args.load()?;
+    let out_pixels = in_pixels.map_channels(|mut pixels| {
+        for _ in 0..args.order(1) {
+            let haar = pixels.map(|low| Haar::new(low * 2.0, 0.0, 0.0, 0.0)).collect();
+            let haar = twiddle_grid::<true>(haar);
+            pixels = from_haar(haar).collect::<Array<Grid, f32>>();
+        }
+        pixels
+    });
+    let depth = args.depth(in_info.depth);
+    args.save("enlarge", &out_pixels, &ImageInfo {depth, ..in_info})
+}
+
+fn quantize(args: &Quantize) -> fvq::Result {
+    let (in_pixels, in_info) = args.io.load()?;
+    let bytes = args.codec.encode(&in_pixels, args.io.order(5), &in_info)?;
+    let header = Header::read(&mut bytes.as_slice())?;
+    let (out_pixels, metadata) = decode_with_metadata(&bytes)?;
+    let depth = args.io.depth(in_info.depth);
+    args.io.save("quantize", &out_pixels, &ImageInfo {depth, colour_space: header.colour_space, metadata})
+}
+
+fn glue(args: &Glue) -> fvq::Result {
+    let model = LumaModel::default();
+    let modes = [Mode::Product, Mode::Family];
+    let mut total_count = 0;
+    let mut total_errors = [0.0; 2];
+    println!("{:>20} {:>10} {:>10} {:>8}", "image", "product", "family", "change");
+    for image_path in &args.image_paths {
+        let pixels = load_image(image_path)?.crop_to_multiple(1 << args.order).luma();
+        let pyramid = Pyramid::from_pixels(args.order, true, pixels);
+        let count = pyramid.size().0 * pyramid.size().1 * ((1 << (2 * args.order)) - 1);
+        let mut errors = [0.0; 2];
+        pyramid.size().each(|yx| {
+            let tree = pyramid.get(Position {level: 0, yx});
+            for (error, mode) in errors.iter_mut().zip(modes) {
+                *error += quantize_tree(args.order, &model, mode, pyramid[yx], &tree).1 as f64;
+            }
+        });
+        let [product, family] = errors.map(|e| e / count as f64);
+        println!("{:>20} {:>10.4} {:>10.4} {:>7.2}%", image_path, product, family, 100.0 * (family / product - 1.0));
+        total_count += count;
+        for (total, error) in total_errors.iter_mut().zip(errors) { *total += error; }
+    }
+    let [product, family] = total_errors.map(|e| e / total_count as f64);
+    println!("{:>20} {:>10.4} {:>10.4} {:>7.2}%", "total", product, family, 100.0 * (family / product - 1.0));
+    Ok(())
+}
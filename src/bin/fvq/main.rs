@@ -0,0 +1,202 @@
+use clap::{Args, Parser, Subcommand};
+use multidimension::{View};
+use fvq::io::{cli, read_image_with_info, Pixels, PixelArray, Metadata, ImageInfo, Depth};
+use fvq::codec::{Header, Layout, distortion, MAGIC};
+
+mod experiment;
+mod stats;
+
+#[derive(Debug, Parser)]
+#[command(about = "Compress images in the FVQ file format, and experiment with the algorithm.")]
+#[command(author, version, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Compress an image into an FVQ file.
+    Encode(Encode),
+
+    /// Decompress an FVQ file into an image.
+    Decode(Decode),
+
+    /// Describe an image or FVQ file.
+    Info(Info),
+
+    /// Measure the difference between two images or FVQ files.
+    Compare(Compare),
+
+    /// Collect statistics about a corpus of images.
+    Stats(stats::Stats),
+
+    /// Draw the difference in luma between two images or FVQ files.
+    Visualize(Visualize),
+
+    /// Run an experiment on an image.
+    #[command(subcommand)]
+    Experiment(experiment::Experiment),
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Args)]
+struct Encode {
+    #[command(flatten)]
+    input: cli::Input,
+
+    /// Output path, or "-" for stdout. Defaults to a file in the temporary
+    /// directory, or stdout if the input is stdin.
+    #[arg(short, long)]
+    out_path: Option<String>,
+
+    /// The order of the wavelet pyramid.
+    #[arg(short = 'n', long, default_value_t = 5)]
+    order: usize,
+
+    #[command(flatten)]
+    codec: cli::Codec,
+}
+
+fn encode(args: &Encode) -> fvq::Result {
+    let (pixels, info) = args.input.load()?;
+    let bytes = args.codec.encode(&pixels, args.order, &info)?;
+    let out_path = match &args.out_path {
+        Some(out_path) => out_path.clone(),
+        None => cli::default_out_path(&args.input.in_path, "encode", "fvq")?,
+    };
+    cli::write_bytes(&out_path, &bytes)
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Args)]
+struct Decode {
+    #[command(flatten)]
+    input: cli::Input,
+
+    #[command(flatten)]
+    output: cli::Output,
+}
+
+fn decode(args: &Decode) -> fvq::Result {
+    let (pixels, info) = args.input.load()?;
+    let depth = args.output.depth(info.depth);
+    args.output.save(&args.input.in_path, "decode", &pixels, &ImageInfo {depth, ..info})
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Args)]
+struct Info {
+    /// Input path, or "-" for stdin.
+    in_path: String,
+}
+
+/// Print the sizes of the items of `metadata`.
+fn print_metadata(metadata: &Metadata) {
+    for (name, data) in [("exif", &metadata.exif), ("xmp", &metadata.xmp), ("icc", &metadata.icc)] {
+        if let Some(data) = data { println!("{} = {} bytes", name, data.len()); }
+    }
+    if let Some(orientation) = metadata.orientation() { println!("orientation = {}", orientation); }
+}
+
+fn info(args: &Info) -> fvq::Result {
+    let bytes = cli::read_bytes(&args.in_path)?;
+    if bytes.starts_with(&MAGIC) {
+        let header = Header::read(&mut bytes.as_slice())?;
+        println!("{:#?}", header);
+        let bpp = (8 * bytes.len()) as f64 / (header.width * header.height) as f64;
+        println!("{} bytes, {:.3} bits per pixel", bytes.len(), bpp);
+        let (_, metadata) = fvq::codec::decode_with_metadata(&bytes)?;
+        print_metadata(&metadata);
+    } else {
+        let (pixels, info) = read_image_with_info(&mut bytes.as_slice(), None)?;
+        let (height, width) = pixels.size();
+        println!("width = {}", width);
+        println!("height = {}", height);
+        println!("layout = {:?}", Layout::of(&pixels));
+        println!("depth = {:?}", info.depth);
+        println!("colour_space = {:?}", info.colour_space);
+        print_metadata(&info.metadata);
+    }
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Args)]
+struct Compare {
+    #[command(flatten)]
+    input: cli::Input,
+
+    /// The path of the image to compare with the input image.
+    other_path: String,
+}
+
+impl Compare {
+    /// Load the two images, which must be the same size.
+    fn load(&self) -> fvq::Result<(Pixels, Pixels)> {
+        let (a, _) = self.input.load()?;
+        let other = cli::Input {in_path: self.other_path.clone(), ..self.input};
+        let (b, _) = other.load()?;
+        if a.size() != b.size() { Err(fvq::Error("Images have different sizes"))?; }
+        Ok((a, b))
+    }
+}
+
+fn compare(args: &Compare) -> fvq::Result {
+    let (a, b) = args.load()?;
+    let (a_channels, b_channels) = (a.channels(), b.channels());
+    if a_channels.len() == b_channels.len() {
+        for (i, (x, y)) in a_channels.iter().zip(&b_channels).enumerate() {
+            let mut total = 0.0;
+            x.zip(y).each(|(x, y)| { total += ((x - y) as f64).powi(2); });
+            println!("channel {}: distortion = {:.3e}", i, total / x.len() as f64);
+        }
+        let d = distortion(&a, &b)?;
+        println!("distortion = {:.3e}, PSNR = {:.2} dB", d, -10.0 * d.log10());
+    }
+    let mut total = 0.0;
+    a.luma().zip(b.luma()).each(|(x, y)| { total += ((x - y) as f64).powi(2); });
+    println!("luma distortion = {:.3e}", total / a.luma().len() as f64);
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Args)]
+struct Visualize {
+    #[command(flatten)]
+    compare: Compare,
+
+    #[command(flatten)]
+    output: cli::Output,
+
+    /// The factor by which to amplify the differences.
+    #[arg(long, default_value_t = 8.0)]
+    gain: f32,
+}
+
+fn visualize(args: &Visualize) -> fvq::Result {
+    let (a, b) = args.compare.load()?;
+    let diff = a.luma().zip(b.luma()).map(|(x, y)| 0.5 + args.gain * (y - x)).collect();
+    let pixels = Pixels::L(PixelArray::from_channels(&[diff]));
+    let depth = args.output.depth(Depth::U8);
+    args.output.save(&args.compare.input.in_path, "visualize", &pixels, &ImageInfo {depth, ..ImageInfo::default()})
+}
+
+// ----------------------------------------------------------------------------
+
+fn main() -> fvq::Result {
+    match &Cli::parse().command {
+        Command::Encode(args) => encode(args),
+        Command::Decode(args) => decode(args),
+        Command::Info(args) => info(args),
+        Command::Compare(args) => compare(args),
+        Command::Stats(args) => stats::run(args),
+        Command::Visualize(args) => visualize(args),
+        Command::Experiment(experiment) => experiment.run(),
+    }
+}
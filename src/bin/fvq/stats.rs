@@ -1,14 +1,12 @@
 use std::collections::{HashMap};
-use clap::{Parser};
-use multidimension::{Size, View, Array};
-use fvq::{Error, Grid, Tree, Position, Pyramid};
-use fvq::io::{load_image, Pixels, L};
-use fvq::quantize::{to_digital, ShiftedBCC, Residual, ALL_RESIDUALS, Chain};
-
-#[derive(Debug, Parser)]
-#[command(about = "Collect statistics about a corpus of images.")]
-#[command(author, version, long_about = None)]
-struct Args {
+use clap::{Args};
+use multidimension::{Size, View};
+use fvq::{Tree, Position, Pyramid};
+use fvq::io::{load_image};
+use fvq::quantize::{to_digital, LumaModel, ShiftedBCC, Residual, ALL_RESIDUALS, Chain};
+
+#[derive(Debug, Args)]
+pub struct Stats {
     /// Filename of a list of image filenames.
     pub list_path: String,
 
@@ -17,7 +15,7 @@ struct Args {
     pub order: Option<usize>,
 }
 
-impl Args {
+impl Stats {
     /// Returns the `order` or the specified default value.
     pub fn order(&self, default_order: usize) -> usize {
         self.order.unwrap_or(default_order)
@@ -81,6 +79,7 @@ impl BCCStatistics {
     /// Increment the [`bcc_counts[bcc]`].
     ///
     /// [`bcc_counts[bcc]`]: Self::bcc_counts
+    #[allow(clippy::len_zero)]
     pub fn count_bcc(&mut self, bcc: ShiftedBCC) {
         let chain = Chain::from_bcc(bcc);
         let chain = chain.apply_symmetry(chain.last_residual.recommend_symmetry());
@@ -109,7 +108,7 @@ impl BCCStatistics {
             let low = pyramid.low[yx];
             let pos = Position {level: 0, yx};
             let tree = pyramid.get(pos);
-            let tree = to_digital(pyramid.order(), low, &tree);
+            let tree = to_digital(pyramid.order(), &LumaModel::default(), low, &tree);
             self.count_tree(&tree);
         });
     }
@@ -117,19 +116,14 @@ impl BCCStatistics {
 
 // ----------------------------------------------------------------------------
 
-fn main() -> fvq::Result {
-    let args = Args::parse();
+pub fn run(args: &Stats) -> fvq::Result {
     let image_paths: Vec<String> = std::fs::read_to_string(&args.list_path)?.lines().map(String::from).collect();
     eprintln!("Collecting statistics from {} images", image_paths.len());
     let order = args.order(5);
     let mut pixel_count = 0;
     let mut statistics = BCCStatistics::default();
     for image_path in &image_paths {
-        let in_pixels = load_image(image_path)?;
-        let in_pixels: Array<Grid, f32> = match in_pixels {
-            Pixels::L(pa) => pa.crop_to_multiple(1 << order).column(L).collect(),
-            _ => Err(Error("Image must only have a luma channel"))?,
-        };
+        let in_pixels = load_image(image_path)?.crop_to_multiple(1 << order).luma();
         pixel_count += in_pixels.len();
         let pyramid = Pyramid::from_pixels(order, true, in_pixels);
         statistics.count_pyramid(&pyramid);
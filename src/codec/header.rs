@@ -0,0 +1,362 @@
+use std::io::{Read, Write};
+
+use crate::{Error, Result, Grid};
+use crate::io::{Pixels, Metadata, Depth};
+use crate::colour::{ColourSpace};
+use crate::quantize::{LumaModel, ContrastSensitivity, Flat, Chroma};
+
+/// The first four bytes of every FVQ file.
+pub const MAGIC: [u8; 4] = *b"FVQ\0";
+
+/// The version of the FVQ file format written by this library.
+///
+/// The format is not yet stable. Files of any other version are rejected.
+pub const VERSION: u16 = 1;
+
+/// The largest supported [`Header::order`]. Larger orders would need tiles of
+/// more than `65536` pixels square.
+pub const MAX_ORDER: usize = 16;
+
+/// The largest supported number of pixels in an image, after padding it to a
+/// whole number of tiles. This also bounds the number of tiles, and so the
+/// memory that a crafted [`Header`] can make the decoder allocate.
+pub const MAX_PIXELS: usize = 1 << 28;
+
+/// The default value of [`Header::alpha_tolerance`].
+pub const ALPHA_TOLERANCE: f32 = 1.0 / 64.0;
+
+/// The default value of [`ChromaOptions::factor`].
+///
+/// The chroma channels of [`YCC`] are already scaled down by a factor of
+/// about two relative to RGB, so this does not need to be large.
+///
+/// [`YCC`]: crate::colour::YCC
+pub const CHROMA_FACTOR: f32 = 1.0;
+
+/// Read exactly `N` bytes.
+fn read_array<const N: usize>(r: &mut impl Read) -> Result<[u8; N]> {
+    let mut buffer = [0; N];
+    r.read_exact(&mut buffer).map_err(|_| Error("Truncated file"))?;
+    Ok(buffer)
+}
+
+// ----------------------------------------------------------------------------
+
+/// Identifies the colour channels of an image.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {L=0, LA=1, RGB=2, RGBA=3}
+
+impl Layout {
+    /// Returns the `Layout` of `pixels`.
+    pub fn of(pixels: &Pixels) -> Self {
+        match pixels {
+            Pixels::L(_) => Layout::L,
+            Pixels::LA(_) => Layout::LA,
+            Pixels::RGB(_) => Layout::RGB,
+            Pixels::RGBA(_) => Layout::RGBA,
+        }
+    }
+
+    fn from_u8(x: u8) -> Result<Self> {
+        Ok(match x {
+            0 => Layout::L,
+            1 => Layout::LA,
+            2 => Layout::RGB,
+            3 => Layout::RGBA,
+            _ => Err(Error("Unknown channel layout"))?,
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// How one chroma channel of a colour image is coded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChromaOptions {
+    /// The number of generations of the finest wavelets that are not coded.
+    /// The decoder sets them to zero, which reduces the resolution of the
+    /// channel by a factor of `1 << truncate`. At most [`Header::order`].
+    pub truncate: usize,
+
+    /// The factor by which the channel is less visible than the luma
+    /// channel. See [`Chroma`].
+    pub factor: f32,
+}
+
+impl Default for ChromaOptions {
+    fn default() -> Self { Self {truncate: 1, factor: CHROMA_FACTOR} }
+}
+
+// ----------------------------------------------------------------------------
+
+/// The fixed-size part at the start of an FVQ file.
+///
+/// On disk, the `Header` is [`MAGIC`], then [`VERSION`] as a `u16`, then the
+/// `width` and `height` as `u32`s, then the `order`, `layout`, `model`,
+/// `colour_space` and `depth` as `u8`s, then `pixels_per_degree` as an `f32`,
+/// then, for each of the two `chroma` channels, its `truncate` as a `u8` and
+/// its `factor` as an `f32`, then `alpha_tolerance` as an `f32`.
+/// All numbers are little-endian. The `Header` is followed by metadata chunks,
+/// which are described by `write_metadata()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+    /// The width of the image in pixels.
+    pub width: usize,
+
+    /// The height of the image in pixels.
+    pub height: usize,
+
+    /// The number of generations of wavelets.
+    pub order: usize,
+
+    /// The colour channels of the image.
+    pub layout: Layout,
+
+    /// The perceptual model used to quantise the image.
+    pub model: LumaModel,
+
+    /// The colour space of the original image. The pixels are coded in
+    /// linear Rec. 709 regardless; this records how to save them.
+    pub colour_space: ColourSpace,
+
+    /// The precision of the original image. This is the default depth at which
+    /// to save the decoded pixels.
+    pub depth: Depth,
+
+    /// The viewing conditions assumed when quantising the image. See
+    /// [`ContrastSensitivity`].
+    pub pixels_per_degree: f32,
+
+    /// How the blue and red chroma channels are coded, if the image has
+    /// colour.
+    pub chroma: [ChromaOptions; 2],
+
+    /// The tolerance of the alpha channel, if any. See [`Flat`].
+    pub alpha_tolerance: f32,
+}
+
+impl Header {
+    /// Returns the size of the image in units of `1 << order` pixels,
+    /// rounded up.
+    pub fn tiles(&self) -> Grid {
+        let quantum = 1 << self.order;
+        (self.height.div_ceil(quantum), self.width.div_ceil(quantum))
+    }
+
+    /// Returns the perceptual model used to quantise the image.
+    pub fn perceptual_model(&self) -> ContrastSensitivity<LumaModel> {
+        ContrastSensitivity {model: self.model, pixels_per_degree: self.pixels_per_degree}
+    }
+
+    /// Returns the perceptual model used to quantise chroma channel `index`
+    /// of a colour image.
+    pub fn chroma_model(&self, index: usize) -> Chroma<ContrastSensitivity<LumaModel>> {
+        Chroma {model: self.perceptual_model(), factor: self.chroma[index].factor}
+    }
+
+    /// Returns the perceptual model used to quantise the alpha channel.
+    pub fn alpha_model(&self) -> Flat { Flat(self.alpha_tolerance) }
+
+    /// Returns an error if any field of `self` is out of range: an empty
+    /// image, an `order` larger than [`MAX_ORDER`], more than [`MAX_PIXELS`]
+    /// pixels after padding to whole tiles, a chroma `truncate`
+    /// larger than `order`, or a tolerance or factor that is not positive and
+    /// finite.
+    pub fn check(&self) -> Result {
+        if self.width == 0 || self.height == 0 { Err(Error("Image is empty"))?; }
+        if self.order > MAX_ORDER { Err(Error("Order is too large"))?; }
+        let (tiles_y, tiles_x) = self.tiles();
+        let padded = tiles_y.checked_mul(tiles_x).and_then(|n| n.checked_mul(1_usize.checked_shl(2 * self.order as u32)?));
+        if padded.is_none_or(|n| n > MAX_PIXELS) { Err(Error("Image is too large"))?; }
+        if !(self.pixels_per_degree >= 0.0 && self.pixels_per_degree.is_finite()) { Err(Error("Invalid pixels per degree"))?; }
+        for chroma in &self.chroma {
+            if chroma.truncate > self.order { Err(Error("Truncation is larger than order"))?; }
+            if !(chroma.factor > 0.0 && chroma.factor.is_finite()) { Err(Error("Invalid chroma factor"))?; }
+        }
+        if !(self.alpha_tolerance > 0.0 && self.alpha_tolerance.is_finite()) { Err(Error("Invalid alpha tolerance"))?; }
+        Ok(())
+    }
+
+    /// Write `self` to `w`.
+    pub fn write(&self, w: &mut impl Write) -> Result {
+        self.check()?;
+        let width = u32::try_from(self.width).map_err(|_| Error("Image is too wide"))?;
+        let height = u32::try_from(self.height).map_err(|_| Error("Image is too tall"))?;
+        let order = u8::try_from(self.order).map_err(|_| Error("Order is too large"))?;
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&width.to_le_bytes())?;
+        w.write_all(&height.to_le_bytes())?;
+        w.write_all(&[order, self.layout as u8, self.model as u8, self.colour_space as u8, self.depth as u8])?;
+        w.write_all(&self.pixels_per_degree.to_le_bytes())?;
+        for chroma in &self.chroma {
+            w.write_all(&[chroma.truncate as u8])?;
+            w.write_all(&chroma.factor.to_le_bytes())?;
+        }
+        w.write_all(&self.alpha_tolerance.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Read a `Self` from `r`.
+    pub fn read(r: &mut impl Read) -> Result<Self> {
+        if read_array(r)? != MAGIC { Err(Error("Not an FVQ file"))?; }
+        if u16::from_le_bytes(read_array(r)?) != VERSION { Err(Error("Unsupported FVQ version"))?; }
+        let width = u32::from_le_bytes(read_array(r)?) as usize;
+        let height = u32::from_le_bytes(read_array(r)?) as usize;
+        let [order, layout, model, colour_space, depth] = read_array(r)?;
+        let order = order as usize;
+        let layout = Layout::from_u8(layout)?;
+        let model = LumaModel::from_u8(model)?;
+        let colour_space = ColourSpace::from_u8(colour_space)?;
+        let depth = Depth::from_u8(depth)?;
+        let pixels_per_degree = f32::from_le_bytes(read_array(r)?);
+        let mut chroma = [ChromaOptions::default(); 2];
+        for c in &mut chroma {
+            let [truncate] = read_array(r)?;
+            let factor = f32::from_le_bytes(read_array(r)?);
+            *c = ChromaOptions {truncate: truncate as usize, factor};
+        }
+        let alpha_tolerance = f32::from_le_bytes(read_array(r)?);
+        let header = Self {width, height, order, layout, model, colour_space, depth, pixels_per_degree, chroma, alpha_tolerance};
+        header.check()?;
+        Ok(header)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// The tags of the metadata chunks that follow the [`Header`].
+const EXIF_TAG: [u8; 4] = *b"EXIF";
+const XMP_TAG: [u8; 4] = *b"XMP ";
+const ICC_TAG: [u8; 4] = *b"ICC ";
+const END_TAG: [u8; 4] = [0; 4];
+
+/// Write `metadata` to `w`.
+///
+/// On disk, each item of metadata is a four-byte tag, then its length as a
+/// little-endian `u32`, then its bytes. The list ends with a tag of four zero
+/// bytes.
+pub(super) fn write_metadata(w: &mut impl Write, metadata: &Metadata) -> Result {
+    for (tag, data) in [(EXIF_TAG, &metadata.exif), (XMP_TAG, &metadata.xmp), (ICC_TAG, &metadata.icc)] {
+        let Some(data) = data else { continue; };
+        let length = u32::try_from(data.len()).map_err(|_| Error("Metadata is too large"))?;
+        w.write_all(&tag)?;
+        w.write_all(&length.to_le_bytes())?;
+        w.write_all(data)?;
+    }
+    w.write_all(&END_TAG)?;
+    Ok(())
+}
+
+/// Read metadata written by `write_metadata()`. Unknown tags are skipped.
+pub(super) fn read_metadata(r: &mut impl Read) -> Result<Metadata> {
+    let mut metadata = Metadata::default();
+    loop {
+        let tag = read_array(r)?;
+        if tag == END_TAG { return Ok(metadata); }
+        let length = u32::from_le_bytes(read_array(r)?) as u64;
+        let mut data = Vec::new();
+        r.take(length).read_to_end(&mut data)?;
+        if data.len() as u64 != length { Err(Error("Truncated file"))?; }
+        match tag {
+            EXIF_TAG => { metadata.exif = Some(data); },
+            XMP_TAG => { metadata.xmp = Some(data); },
+            ICC_TAG => { metadata.icc = Some(data); },
+            _ => {},
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let header = Header {
+            width: 640,
+            height: 480,
+            order: 5,
+            layout: Layout::RGBA,
+            model: LumaModel::SquareRoot,
+            colour_space: ColourSpace::DisplayP3,
+            depth: Depth::U16,
+            pixels_per_degree: 40.0,
+            chroma: [ChromaOptions {truncate: 2, factor: 1.5}, ChromaOptions {truncate: 0, factor: 3.0}],
+            alpha_tolerance: 0.01,
+        };
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], &MAGIC);
+        let header2 = Header::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header, header2);
+        assert_eq!(header.tiles(), (15, 20));
+        assert_eq!(Header {width: 641, height: 479, ..header}.tiles(), (15, 21));
+    }
+
+    #[test]
+    fn metadata() {
+        let metadata = Metadata {exif: Some(b"II*\0".to_vec()), xmp: None, icc: Some(vec![1; 300])};
+        let mut bytes = Vec::new();
+        write_metadata(&mut bytes, &metadata).unwrap();
+        bytes.extend(b"rest");
+        let mut r = bytes.as_slice();
+        assert_eq!(read_metadata(&mut r).unwrap(), metadata);
+        assert_eq!(r, b"rest");
+        assert!(read_metadata(&mut &bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let header = Header {
+            width: 640,
+            height: 480,
+            order: 5,
+            layout: Layout::L,
+            model: LumaModel::default(),
+            colour_space: ColourSpace::default(),
+            depth: Depth::default(),
+            pixels_per_degree: 0.0,
+            chroma: [ChromaOptions::default(); 2],
+            alpha_tolerance: ALPHA_TOLERANCE,
+        };
+        assert!(header.check().is_ok());
+        assert!(Header {order: MAX_ORDER + 1, ..header}.check().is_err());
+        assert!(Header {width: 0, ..header}.check().is_err());
+        assert!(Header {width: 1 << 14, height: 1 << 14, ..header}.check().is_ok());
+        assert!(Header {width: (1 << 14) + 1, height: 1 << 14, ..header}.check().is_err());
+        assert!(Header {width: u32::MAX as usize, height: u32::MAX as usize, ..header}.check().is_err());
+        assert!(Header {width: 1, height: 1, order: MAX_ORDER, ..header}.check().is_err());
+        assert!(Header {alpha_tolerance: 0.0, ..header}.check().is_err());
+        assert!(Header {chroma: [ChromaOptions {truncate: 1, factor: f32::NAN}; 2], ..header}.check().is_err());
+        // A crafted file with order 63.
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        bytes[14] = 63;
+        assert!(Header::read(&mut bytes.as_slice()).is_err());
+        // A crafted file with an unknown depth.
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        bytes[18] = 3;
+        assert!(Header::read(&mut bytes.as_slice()).is_err());
+        // A crafted file with zero height.
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        bytes[10..14].copy_from_slice(&[0; 4]);
+        assert!(Header::read(&mut bytes.as_slice()).is_err());
+        // A crafted file with the largest possible width and height.
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        bytes[6..14].copy_from_slice(&[0xFF; 8]);
+        assert!(Header::read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn bad_magic() {
+        let bytes = b"PNG\0\x01\x00";
+        assert!(Header::read(&mut bytes.as_slice()).is_err());
+    }
+}
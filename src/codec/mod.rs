@@ -0,0 +1,506 @@
+use std::io::{Read, Write};
+use multidimension::{Size, View, Array};
+
+use super::{Error, Result, Grid, Position, Pyramid};
+use super::io::{Pixels, PixelArray, Metadata, Depth, L, LA, RGB, RGBA};
+use super::colour::{YCC, ColourSpace, to_ycc, from_ycc};
+use super::quantize::{PerceptualModel, LumaModel, to_digital_rd, from_digital, low_to_digital, low_from_digital};
+use super::encode::{ByteWriter, ByteReader, Reader, Writer, TreeModel, LowModel};
+
+mod header;
+pub use header::{MAGIC, VERSION, MAX_ORDER, MAX_PIXELS, CHROMA_FACTOR, ALPHA_TOLERANCE, Layout, ChromaOptions, Header};
+use header::{write_metadata, read_metadata};
+
+mod target;
+pub use target::{Target, Encoded, distortion, encode_to_target};
+
+// ----------------------------------------------------------------------------
+
+/// Parameters of [`encode()`].
+///
+/// `encode()` returns an error if any of them is out of range. See
+/// [`Header::check()`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The number of generations of wavelets.
+    pub order: usize,
+
+    /// The perceptual model.
+    pub model: LumaModel,
+
+    /// The colour space of the original image, which is recorded in the
+    /// [`Header`]. The pixels must nonetheless be linear Rec. 709.
+    pub colour_space: ColourSpace,
+
+    /// The precision of the original image, which is recorded in the
+    /// [`Header`].
+    pub depth: Depth,
+
+    /// The number of pixels per degree of visual angle at the intended viewing
+    /// distance. Larger values quantise fine detail more coarsely. `0.0`
+    /// treats all levels and orientations alike. See
+    /// [`ContrastSensitivity`](crate::quantize::ContrastSensitivity).
+    pub pixels_per_degree: f32,
+
+    /// How the blue and red chroma channels of colour images are coded.
+    pub chroma: [ChromaOptions; 2],
+
+    /// The tolerance of the alpha channel of images that have one. Smaller
+    /// values are more accurate.
+    pub alpha_tolerance: f32,
+
+    /// The number of units of distortion that are worth one bit. Larger values
+    /// make smaller files. See [`to_digital_rd()`].
+    pub lambda: f32,
+
+    /// The EXIF, XMP and ICC data to store in the file. See
+    /// [`decode_with_metadata()`].
+    pub metadata: Metadata,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            order: 5,
+            model: LumaModel::default(),
+            colour_space: ColourSpace::default(),
+            depth: Depth::default(),
+            pixels_per_degree: 0.0,
+            chroma: Default::default(),
+            alpha_tolerance: ALPHA_TOLERANCE,
+            lambda: 0.0,
+            metadata: Metadata::default(),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Write one channel of an image, which must be `header.tiles()` tiles.
+///
+/// - truncate - the number of generations of the finest wavelets to omit.
+fn write_channel(
+    w: &mut impl Write,
+    header: &Header,
+    options: &Options,
+    perceptual_model: &impl PerceptualModel,
+    truncate: usize,
+    pixels: Array<Grid, f32>,
+) -> Result {
+    let mut pyramid = Pyramid::from_pixels(header.order, true, pixels);
+    let (digital, low) = low_to_digital(header.order, perceptual_model, &pyramid.low);
+    pyramid.low = low;
+    let depth = header.order - truncate;
+    let mut model = TreeModel::new(depth, header.tiles());
+    let mut writer = Writer::new(ByteWriter::new(w));
+    LowModel::default().write_low(&mut writer, &digital);
+    pyramid.size().each(|yx| {
+        let tree = pyramid.get(Position {level: 0, yx}).truncate(depth);
+        let tree = to_digital_rd(header.order, perceptual_model, pyramid[yx], &tree, options.lambda, &model);
+        model.write_tree(&mut writer, yx, &tree);
+    });
+    writer.close().finish()?;
+    Ok(())
+}
+
+/// Read one channel of an image written by `write_channel()`.
+///
+/// The omitted wavelets are set to zero.
+fn read_channel(
+    r: &mut impl Read,
+    header: &Header,
+    perceptual_model: &impl PerceptualModel,
+    truncate: usize,
+) -> Result<Array<Grid, f32>> {
+    let tiles = header.tiles();
+    let mut reader = Reader::new(ByteReader::new(r));
+    let digital = match LowModel::default().read_low(&mut reader, tiles) {
+        Ok(digital) => digital,
+        Err(e) => {
+            reader.close().finish()?;
+            return Err(e);
+        },
+    };
+    let low = low_from_digital(header.order, perceptual_model, &digital);
+    let mut pyramid = Pyramid::from_low(header.order, low);
+    let mut model = TreeModel::new(header.order - truncate, tiles);
+    for y in 0..tiles.0 {
+        for x in 0..tiles.1 {
+            let yx = (y, x);
+            let Some(tree) = model.read_tree(&mut reader, yx) else {
+                reader.close().finish()?;
+                Err(Error("Truncated file"))?
+            };
+            let tree = from_digital(header.order, perceptual_model, pyramid[yx], &tree);
+            pyramid.set(Position {level: 0, yx}, &tree);
+        }
+    }
+    reader.close().finish()?;
+    Ok(pyramid.to_pixels(true))
+}
+
+// ----------------------------------------------------------------------------
+
+/// Decoded alpha values smaller than this are treated as fully transparent.
+const MIN_ALPHA: f32 = 1.0 / 512.0;
+
+/// Multiply `colour` by `alpha`, so that fully transparent pixels are zero
+/// and cost nothing to code.
+fn premultiply(colour: Array<Grid, f32>, alpha: &Array<Grid, f32>) -> Array<Grid, f32> {
+    colour.zip(alpha).map(|(c, a)| c * a).collect()
+}
+
+/// The inverse of `premultiply()`.
+///
+/// Fully transparent pixels are set to zero.
+fn unpremultiply(colour: Array<Grid, f32>, alpha: &Array<Grid, f32>) -> Array<Grid, f32> {
+    colour.zip(alpha).map(|(c, a)| if a < MIN_ALPHA { 0.0 } else { c / a }).collect()
+}
+
+/// Write the channels of a colour image.
+fn write_colour(w: &mut impl Write, header: &Header, options: &Options, pixels: &PixelArray<RGB>) -> Result {
+    let ycc = to_ycc(pixels);
+    write_channel(w, header, options, &header.perceptual_model(), 0, ycc.channel(YCC::Luma))?;
+    for (i, c) in [YCC::Blue, YCC::Red].into_iter().enumerate() {
+        write_channel(w, header, options, &header.chroma_model(i), header.chroma[i].truncate, ycc.channel(c))?;
+    }
+    Ok(())
+}
+
+/// Read the channels written by `write_colour()`.
+fn read_colour(r: &mut impl Read, header: &Header) -> Result<[Array<Grid, f32>; 3]> {
+    let y = read_channel(r, header, &header.perceptual_model(), 0)?;
+    let cb = read_channel(r, header, &header.chroma_model(0), header.chroma[0].truncate)?;
+    let cr = read_channel(r, header, &header.chroma_model(1), header.chroma[1].truncate)?;
+    let rgb = from_ycc(&PixelArray::from_channels(&[y, cb, cr]));
+    Ok([RGB::Red, RGB::Green, RGB::Blue].map(|c| rgb.channel(c)))
+}
+
+/// Read an alpha channel, and clamp it to the range `0.0` to `1.0`.
+fn read_alpha(r: &mut impl Read, header: &Header) -> Result<Array<Grid, f32>> {
+    let alpha = read_channel(r, header, &header.alpha_model(), 0)?;
+    Ok(alpha.map(|a| a.clamp(0.0, 1.0)).collect())
+}
+
+// ----------------------------------------------------------------------------
+
+/// Compress `pixels` into the FVQ file format.
+///
+/// The image may be any size. It is padded to a multiple of
+/// `1 << options.order` pixels in each dimension before coding, and cropped
+/// again by [`decode()`]. Colour images are coded as a luma channel and two chroma
+/// channels. The alpha channel, if any, is coded last, using a
+/// [`Flat`](crate::quantize::Flat) perceptual model, and the other channels
+/// are premultiplied by it.
+pub fn encode(pixels: &Pixels, options: &Options) -> Result<Vec<u8>> {
+    let (height, width) = pixels.size();
+    let header = Header {
+        width,
+        height,
+        order: options.order,
+        layout: Layout::of(pixels),
+        model: options.model,
+        colour_space: options.colour_space,
+        depth: options.depth,
+        pixels_per_degree: options.pixels_per_degree,
+        chroma: options.chroma,
+        alpha_tolerance: options.alpha_tolerance,
+    };
+    header.check()?;
+    if !(options.lambda >= 0.0 && options.lambda.is_finite()) { Err(Error("Invalid lambda"))?; }
+    let pixels = pixels.pad_to_multiple(1 << options.order);
+    let mut bytes = Vec::new();
+    header.write(&mut bytes)?;
+    write_metadata(&mut bytes, &options.metadata)?;
+    let w = &mut bytes;
+    match pixels {
+        Pixels::L(pa) => {
+            write_channel(w, &header, options, &header.perceptual_model(), 0, pa.channel(L))?;
+        },
+        Pixels::LA(pa) => {
+            let alpha = pa.channel(LA::Alpha);
+            let luma = premultiply(pa.channel(LA::Luma), &alpha);
+            write_channel(w, &header, options, &header.perceptual_model(), 0, luma)?;
+            write_channel(w, &header, options, &header.alpha_model(), 0, alpha)?;
+        },
+        Pixels::RGB(pa) => {
+            write_colour(w, &header, options, &pa)?;
+        },
+        Pixels::RGBA(pa) => {
+            let alpha = pa.channel(RGBA::Alpha);
+            let rgb = [RGBA::Red, RGBA::Green, RGBA::Blue].map(|c| premultiply(pa.channel(c), &alpha));
+            write_colour(w, &header, options, &PixelArray::from_channels(&rgb))?;
+            write_channel(w, &header, options, &header.alpha_model(), 0, alpha)?;
+        },
+    }
+    Ok(bytes)
+}
+
+/// Decompress an FVQ file.
+pub fn decode(bytes: &[u8]) -> Result<Pixels> {
+    Ok(decode_with_metadata(bytes)?.0)
+}
+
+/// Decompress an FVQ file, and also return the [`Metadata`] stored in it.
+pub fn decode_with_metadata(bytes: &[u8]) -> Result<(Pixels, Metadata)> {
+    let mut r = bytes;
+    let header = Header::read(&mut r)?;
+    let metadata = read_metadata(&mut r)?;
+    let pixels = match header.layout {
+        Layout::L => {
+            let y = read_channel(&mut r, &header, &header.perceptual_model(), 0)?;
+            Pixels::L(PixelArray::from_channels(&[y]))
+        },
+        Layout::LA => {
+            let y = read_channel(&mut r, &header, &header.perceptual_model(), 0)?;
+            let alpha = read_alpha(&mut r, &header)?;
+            Pixels::LA(PixelArray::from_channels(&[unpremultiply(y, &alpha), alpha]))
+        },
+        Layout::RGB => {
+            Pixels::RGB(PixelArray::from_channels(&read_colour(&mut r, &header)?))
+        },
+        Layout::RGBA => {
+            let [red, green, blue] = read_colour(&mut r, &header)?;
+            let alpha = read_alpha(&mut r, &header)?;
+            let [red, green, blue] = [red, green, blue].map(|c| unpremultiply(c, &alpha));
+            Pixels::RGBA(PixelArray::from_channels(&[red, green, blue, alpha]))
+        },
+    };
+    Ok((pixels.crop((header.height, header.width)), metadata))
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::io::{load_image};
+    use crate::quantize::{to_digital};
+
+    #[test]
+    fn round_trip() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let options = Options::default();
+        let bytes = encode(&pixels, &options).unwrap();
+        let decoded = match decode(&bytes).unwrap() {
+            Pixels::L(pa) => pa.column(L).collect::<Array<Grid, f32>>(),
+            _ => panic!("Not a luma image"),
+        };
+        // Compare with quantising the image without going through bytes.
+        let in_pixels: Array<Grid, f32> = match pixels {
+            Pixels::L(pa) => pa.column(L).collect(),
+            _ => panic!("Not a luma image"),
+        };
+        let mut pyramid = Pyramid::from_pixels(options.order, true, in_pixels);
+        pyramid.low = low_to_digital(options.order, &options.model, &pyramid.low).1;
+        pyramid.size().each(|yx| {
+            let pos = Position {level: 0, yx};
+            let tree = to_digital(options.order, &options.model, pyramid[yx], &pyramid.get(pos));
+            let tree = from_digital(options.order, &options.model, pyramid[yx], &tree);
+            pyramid.set(pos, &tree);
+        });
+        let expected = pyramid.to_pixels(true);
+        assert_eq!(decoded.size(), expected.size());
+        decoded.zip(expected).each(|(x, y)| { assert!((x - y).abs() < 1e-6); });
+    }
+
+    #[test]
+    fn truncated() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let bytes = encode(&pixels, &Options::default()).unwrap();
+        assert!(decode(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    /// Encodes `pixels` with each of `options`, asserts that each file is
+    /// smaller than the last, and returns each file and its decoded image.
+    fn encode_each(pixels: &Pixels, options: impl IntoIterator<Item=Options>) -> Vec<(Vec<u8>, Pixels)> {
+        let ret: Vec<_> = options.into_iter().map(|options| {
+            let bytes = encode(pixels, &options).unwrap();
+            let decoded = decode(&bytes).unwrap();
+            (bytes, decoded)
+        }).collect();
+        let sizes: Vec<usize> = ret.iter().map(|(bytes, _)| bytes.len()).collect();
+        assert!(sizes.windows(2).all(|w| w[0] > w[1]), "{:?}", sizes);
+        ret
+    }
+
+    #[test]
+    fn lambda() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let encoded = encode_each(&pixels, [0.0, 1.0, 4.0].map(|lambda| Options {lambda, ..Options::default()}));
+        // Smaller files cost more distortion.
+        let distortions: Vec<f64> = encoded.iter().map(|(_, decoded)| distortion(&pixels, decoded).unwrap()).collect();
+        assert!(distortions.windows(2).all(|w| w[0] < w[1]), "{:?}", distortions);
+    }
+
+    #[test]
+    fn pixels_per_degree() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let values = [0.0, 30.0, 60.0];
+        let encoded = encode_each(&pixels, values.map(|pixels_per_degree| Options {pixels_per_degree, ..Options::default()}));
+        for ((bytes, decoded), pixels_per_degree) in encoded.into_iter().zip(values) {
+            assert_eq!(Header::read(&mut bytes.as_slice()).unwrap().pixels_per_degree, pixels_per_degree);
+            if pixels_per_degree == 0.0 { continue; }
+            // The decoder uses the value recorded in the header.
+            let mut altered = bytes.clone();
+            altered[18..22].copy_from_slice(&(2.0 * pixels_per_degree).to_le_bytes());
+            assert!(distortion(&decoded, &decode(&altered).unwrap()).unwrap() > 0.0);
+        }
+    }
+
+    /// Returns a colour image made by tinting `standard/lenna.png`.
+    fn colour_image() -> PixelArray<RGB> {
+        let luma = match load_image("standard/lenna.png").unwrap() {
+            Pixels::L(pa) => pa.channel(L),
+            _ => panic!("Not a luma image"),
+        };
+        let r = (&luma).map(|l| l);
+        let g = (&luma).map(|l| l * l);
+        let b = (&luma).map(|l| 1.0 - l);
+        PixelArray::from_channels(&[r.collect(), g.collect(), b.collect()])
+    }
+
+    #[test]
+    fn colour() {
+        let pixels = Pixels::RGB(colour_image());
+        let bytes = encode(&pixels, &Options::default()).unwrap();
+        assert_eq!(Header::read(&mut bytes.as_slice()).unwrap().layout, Layout::RGB);
+        let Pixels::RGB(decoded) = decode(&bytes).unwrap() else { panic!("Not a colour image"); };
+        let Pixels::RGB(pixels) = pixels else { unreachable!() };
+        assert_eq!(decoded.size(), pixels.size());
+        let mut total = 0.0;
+        (&pixels.0).zip(&decoded.0).each(|(x, y)| { total += (x - y) * (x - y); });
+        let mean = total / (pixels.0.len() as f32);
+        assert!(mean < 1e-3, "{}", mean);
+    }
+
+    /// Returns the mean square of the finest wavelet coefficients at
+    /// each of the finest `levels` levels of the `Pyramid` of order `order` of
+    /// `channel`, finest first.
+    fn high_energy(order: usize, levels: usize, channel: Array<Grid, f32>) -> Vec<f32> {
+        let pyramid = Pyramid::from_pixels(order, true, channel);
+        pyramid.highs.iter().rev().take(levels).map(|high| {
+            let mut total = 0.0;
+            high.each(|x| { total += x * x; });
+            total / high.len() as f32
+        }).collect()
+    }
+
+    #[test]
+    fn chroma_truncate() {
+        let pixels = Pixels::RGB(colour_image());
+        let encoded = encode_each(&pixels, [0, 1, 2].map(|truncate| {
+            Options {chroma: [ChromaOptions {truncate, factor: CHROMA_FACTOR}; 2], ..Options::default()}
+        }));
+        for (truncate, (_, decoded)) in encoded.into_iter().enumerate() {
+            let Pixels::RGB(decoded) = decoded else { panic!("Not a colour image"); };
+            assert_eq!(decoded.pixel_size(), pixels.size());
+            // The finest `truncate` levels of the decoded chroma are blank.
+            let ycc = to_ycc(&decoded);
+            for c in [YCC::Blue, YCC::Red] {
+                let energy = high_energy(Options::default().order, 3, ycc.channel(c));
+                for (level, &e) in energy.iter().enumerate() {
+                    assert_eq!(e < 1e-9, level < truncate, "{} {:?}", truncate, energy);
+                }
+            }
+        }
+        let chroma = [ChromaOptions {truncate: 6, factor: CHROMA_FACTOR}; 2];
+        assert!(encode(&pixels, &Options {chroma, ..Options::default()}).is_err());
+    }
+
+    /// Returns `colour_image()` with an alpha channel that is transparent on
+    /// the left and opaque on the right, with a soft edge between.
+    ///
+    /// - colour - replaces the colour of the transparent pixels.
+    fn alpha_image(colour: f32) -> PixelArray<RGBA> {
+        let pixels = colour_image();
+        let (_, width) = pixels.pixel_size();
+        let alpha = Array::from_fn(pixels.pixel_size(), |(_, x)| {
+            ((x as f32 - 0.5 * width as f32) / 8.0).clamp(0.0, 1.0)
+        });
+        let rgb = [RGB::Red, RGB::Green, RGB::Blue].map(|c| {
+            pixels.channel(c).zip(&alpha).map(|(x, a)| if a == 0.0 { colour } else { x }).collect()
+        });
+        let [red, green, blue] = rgb;
+        PixelArray::from_channels(&[red, green, blue, alpha])
+    }
+
+    #[test]
+    fn alpha() {
+        let pixels = alpha_image(0.0);
+        let bytes = encode(&Pixels::RGBA(pixels), &Options::default()).unwrap();
+        // Transparent pixels cost nothing.
+        let bytes2 = encode(&Pixels::RGBA(alpha_image(0.7)), &Options::default()).unwrap();
+        assert_eq!(bytes, bytes2);
+        let Pixels::RGBA(decoded) = decode(&bytes).unwrap() else { panic!("Not an RGBA image"); };
+        let pixels = alpha_image(0.0);
+        let alpha = pixels.channel(RGBA::Alpha);
+        let decoded_alpha = decoded.channel(RGBA::Alpha);
+        let mut total = 0.0;
+        (&alpha).zip(&decoded_alpha).each(|(a, b)| {
+            assert!((0.0..=1.0).contains(&b));
+            total += (a - b) * (a - b);
+        });
+        let mean = total / alpha.len() as f32;
+        assert!(mean < ALPHA_TOLERANCE * ALPHA_TOLERANCE, "{}", mean);
+        // Luma + alpha also works.
+        let luma = PixelArray::<LA>::from_channels(&[pixels.channel(RGBA::Green), alpha]);
+        let bytes = encode(&Pixels::LA(luma), &Options::default()).unwrap();
+        assert!(matches!(decode(&bytes).unwrap(), Pixels::LA(_)));
+    }
+
+    #[test]
+    fn tiny_tolerance() {
+        // Coefficients far larger than the tolerance are clamped rather than
+        // making chains that are too long to code.
+        let pixels = colour_image().crop((64, 64));
+        let luma = PixelArray::<LA>::from_channels(&[pixels.channel(RGB::Green), pixels.channel(RGB::Red)]);
+        let options = Options {alpha_tolerance: 1e-8, ..Options::default()};
+        let bytes = encode(&Pixels::LA(luma), &options).unwrap();
+        assert!(matches!(decode(&bytes).unwrap(), Pixels::LA(_)));
+    }
+
+    #[test]
+    fn odd_size() {
+        let pixels = colour_image().crop((101, 67));
+        let bytes = encode(&Pixels::RGB(pixels), &Options::default()).unwrap();
+        let header = Header::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!((header.height, header.width), (101, 67));
+        assert_eq!(header.tiles(), (4, 3));
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.size(), (101, 67));
+    }
+
+    #[test]
+    fn invalid_options() {
+        let pixels = Pixels::RGB(colour_image().crop((32, 32)));
+        assert!(encode(&pixels, &Options {order: 40, ..Options::default()}).is_err());
+        assert!(encode(&pixels, &Options {alpha_tolerance: 0.0, ..Options::default()}).is_err());
+        assert!(encode(&pixels, &Options {lambda: f32::NAN, ..Options::default()}).is_err());
+        let chroma = [ChromaOptions {truncate: 1, factor: -1.0}; 2];
+        assert!(encode(&pixels, &Options {chroma, ..Options::default()}).is_err());
+        assert!(encode(&Pixels::RGB(colour_image().crop((0, 32))), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn metadata() {
+        let pixels = Pixels::RGB(colour_image().crop((32, 32)));
+        let metadata = Metadata {exif: None, xmp: Some(b"<x/>".to_vec()), icc: Some(vec![7; 100])};
+        let plain = encode(&pixels, &Options::default()).unwrap();
+        let bytes = encode(&pixels, &Options {metadata: metadata.clone(), ..Options::default()}).unwrap();
+        let (decoded, metadata2) = decode_with_metadata(&bytes).unwrap();
+        assert_eq!(metadata2, metadata);
+        let decoded2 = decode(&plain).unwrap();
+        (&decoded.channels()[0]).zip(&decoded2.channels()[0]).each(|(x, y)| assert_eq!(x, y));
+    }
+
+    #[test]
+    fn depth() {
+        let pixels = Pixels::RGB(colour_image().crop((32, 32)));
+        for depth in [Depth::U8, Depth::U16, Depth::F32] {
+            let bytes = encode(&pixels, &Options {depth, ..Options::default()}).unwrap();
+            assert_eq!(Header::read(&mut bytes.as_slice()).unwrap().depth, depth);
+        }
+    }
+}
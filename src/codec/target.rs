@@ -0,0 +1,156 @@
+use multidimension::{View};
+
+use crate::{Error, Result};
+use crate::io::{Pixels};
+use super::{Options, Header, encode, decode};
+
+/// The largest value of [`Options::lambda`] tried by [`encode_to_target()`].
+const MAX_LAMBDA: f32 = 1e6;
+
+/// The maximum number of times [`encode_to_target()`] calls [`encode()`].
+const MAX_ATTEMPTS: usize = 30;
+
+/// A compressed size to aim for.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    /// The number of bits per pixel of the image.
+    BitsPerPixel(f64),
+
+    /// The size of the file in bytes.
+    Bytes(usize),
+}
+
+impl Target {
+    /// Returns the number of bytes allowed for an image described by
+    /// `header`.
+    fn bytes(self, header: &Header) -> f64 {
+        match self {
+            Target::BitsPerPixel(bpp) => bpp * (header.width * header.height) as f64 / 8.0,
+            Target::Bytes(bytes) => bytes as f64,
+        }
+    }
+}
+
+/// The result of [`encode_to_target()`].
+#[derive(Debug, Clone)]
+pub struct Encoded {
+    /// The FVQ file.
+    pub bytes: Vec<u8>,
+
+    /// The value of [`Options::lambda`] that was used.
+    pub lambda: f32,
+
+    /// The number of bits per pixel of the image.
+    pub bits_per_pixel: f64,
+
+    /// The mean squared difference between the pixels of the image
+    /// and the decoded pixels, over all channels.
+    pub distortion: f64,
+}
+
+/// Returns the mean squared difference between the channels of two images of
+/// the same size and layout.
+pub fn distortion(a: &Pixels, b: &Pixels) -> Result<f64> {
+    let (a, b) = (a.channels(), b.channels());
+    if a.len() != b.len() { Err(Error("Images have different channels"))?; }
+    let mut total = 0.0;
+    let mut count = 0;
+    for (a, b) in a.iter().zip(&b) {
+        if a.size() != b.size() { Err(Error("Images have different sizes"))?; }
+        a.zip(b).each(|(x, y)| { total += ((x - y) as f64).powi(2); });
+        count += a.len();
+    }
+    Ok(total / count as f64)
+}
+
+/// Construct an [`Encoded`] from the output of `encode()`.
+fn encoded(pixels: &Pixels, bytes: Vec<u8>, lambda: f32) -> Result<Encoded> {
+    let header = Header::read(&mut bytes.as_slice())?;
+    let bits_per_pixel = (8 * bytes.len()) as f64 / (header.width * header.height) as f64;
+    let distortion = distortion(pixels, &decode(&bytes)?)?;
+    Ok(Encoded {bytes, lambda, bits_per_pixel, distortion})
+}
+
+/// Compress `pixels` into the FVQ file format, choosing
+/// [`Options::lambda`] so that the file is as large as possible without
+/// exceeding `target`.
+///
+/// The search stops when the file is within `tolerance` (a fraction, e.g.
+/// `0.02`) of `target`. If even the smallest file exceeds `target`, it is
+/// returned anyway.
+///
+/// `options.lambda` is ignored.
+pub fn encode_to_target(pixels: &Pixels, options: &Options, target: Target, tolerance: f64) -> Result<Encoded> {
+    let try_lambda = |lambda: f32| encode(pixels, &Options {lambda, ..options.clone()});
+    let mut best = try_lambda(0.0)?;
+    let header = Header::read(&mut best.as_slice())?;
+    let target = target.bytes(&header);
+    let mut best_lambda = 0.0;
+    if best.len() as f64 > target {
+        // Find an upper bound.
+        let mut low = 0.0;
+        let mut high = 1.0;
+        let mut attempts = 0;
+        loop {
+            let bytes = try_lambda(high)?;
+            attempts += 1;
+            if bytes.len() as f64 <= target || high >= MAX_LAMBDA {
+                best = bytes;
+                best_lambda = high;
+                break;
+            }
+            low = high;
+            high *= 4.0;
+        }
+        // Bisect.
+        while
+            attempts < MAX_ATTEMPTS &&
+            (best.len() as f64) < target * (1.0 - tolerance) &&
+            high - low > high * 1e-3
+        {
+            let lambda = if low == 0.0 { 0.5 * high } else { (low * high).sqrt() };
+            let bytes = try_lambda(lambda)?;
+            attempts += 1;
+            if bytes.len() as f64 <= target {
+                best = bytes;
+                best_lambda = lambda;
+                high = lambda;
+            } else {
+                low = lambda;
+            }
+        }
+    }
+    encoded(pixels, best, best_lambda)
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::io::{load_image};
+
+    #[test]
+    fn bits_per_pixel() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let options = Options::default();
+        let mut distortion = 0.0;
+        for bpp in [0.3, 0.15] {
+            let encoded = encode_to_target(&pixels, &options, Target::BitsPerPixel(bpp), 0.05).unwrap();
+            assert!(encoded.bits_per_pixel <= bpp);
+            assert!(encoded.bits_per_pixel > 0.95 * bpp);
+            assert!(encoded.distortion > distortion);
+            distortion = encoded.distortion;
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let options = Options::default();
+        let encoded = encode_to_target(&pixels, &options, Target::Bytes(100_000), 0.05).unwrap();
+        assert_eq!(encoded.lambda, 0.0);
+        assert_eq!(encoded.bytes, encode(&pixels, &options).unwrap());
+    }
+}
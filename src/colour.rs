@@ -0,0 +1,277 @@
+use std::mem::{transmute};
+
+use multidimension::{NonTuple, StaticIndex, View, Array};
+
+use super::{Grid};
+use super::io::{PixelArray, Channels, RGB};
+
+/// The weights of the red, green and blue channels in the luma channel.
+pub const LUMA_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// The factor by which blue minus luma is divided to make the `Blue` channel.
+const BLUE_SCALE: f32 = 2.0 * (1.0 - LUMA_WEIGHTS[2]);
+
+/// The factor by which red minus luma is divided to make the `Red` channel.
+const RED_SCALE: f32 = 2.0 * (1.0 - LUMA_WEIGHTS[0]);
+
+// ----------------------------------------------------------------------------
+
+/// Indicates a channel of a luma + chroma image.
+///
+/// This is the linear-light analogue of Rec. 709 YCbCr: `Luma` is the
+/// luminance, and `Blue` and `Red` are the blue-yellow and red-cyan opponent
+/// channels, scaled to lie in `[-0.5, 0.5]` for colours in the RGB cube. The
+/// conversion is linear, so that the wavelet transform acts on light, not on
+/// perceived brightness.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum YCC {Luma=0, Blue=1, Red=2}
+
+impl NonTuple for YCC {}
+
+impl StaticIndex for YCC {
+    const ALL: &'static [Self] = &[YCC::Luma, YCC::Blue, YCC::Red];
+    fn to_usize(self) -> usize { self as usize }
+    fn from_usize(index: usize) -> Self { unsafe { transmute(index as u8) } }
+}
+
+impl Channels for YCC {
+    fn is_alpha(self) -> bool { false }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Returns the luma of a colour.
+pub fn luma(r: f32, g: f32, b: f32) -> f32 {
+    let [wr, wg, wb] = LUMA_WEIGHTS;
+    wr * r + wg * g + wb * b
+}
+
+/// Converts a colour from linear RGB to luma + chroma.
+pub fn rgb_to_ycc([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let y = luma(r, g, b);
+    [y, (b - y) / BLUE_SCALE, (r - y) / RED_SCALE]
+}
+
+/// Converts a colour from luma + chroma to linear RGB.
+pub fn ycc_to_rgb([y, cb, cr]: [f32; 3]) -> [f32; 3] {
+    let [wr, wg, wb] = LUMA_WEIGHTS;
+    let r = y + RED_SCALE * cr;
+    let b = y + BLUE_SCALE * cb;
+    let g = (y - wr * r - wb * b) / wg;
+    [r, g, b]
+}
+
+/// Applies `f` to every pixel of `pixels`.
+fn map_pixels<C: Channels, D: Channels>(
+    pixels: &PixelArray<C>,
+    f: impl Fn([f32; 3]) -> [f32; 3],
+) -> PixelArray<D> {
+    assert_eq!(C::NUM_CHANNELS, 3);
+    assert_eq!(D::NUM_CHANNELS, 3);
+    let size = pixels.pixel_size();
+    let out: Array<Grid, [f32; 3]> = Array::from_fn(size, |yx| {
+        f([0, 1, 2].map(|i| pixels.at((yx, C::ALL[i]))))
+    });
+    PixelArray(Array::from_fn((size, ()), |(yx, d): (Grid, D)| out.at(yx)[StaticIndex::to_usize(d)]))
+}
+
+/// Converts an image from linear RGB to luma + chroma.
+pub fn to_ycc(pixels: &PixelArray<RGB>) -> PixelArray<YCC> { map_pixels(pixels, rgb_to_ycc) }
+
+/// Converts an image from luma + chroma to linear RGB.
+pub fn from_ycc(pixels: &PixelArray<YCC>) -> PixelArray<RGB> { map_pixels(pixels, ycc_to_rgb) }
+
+// ----------------------------------------------------------------------------
+
+/// An RGB colour space of an image file, comprising a transfer function and
+/// a set of primaries. All use the D65 white point.
+///
+/// In memory, images are always linear Rec. 709 (the primaries of sRGB), which
+/// is the canonical space. Colours outside its gamut have components outside
+/// the range `0.0` to `1.0`.
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ColourSpace {
+    /// sRGB: the sRGB transfer function and the Rec. 709 primaries.
+    #[default]
+    SRGB = 0,
+
+    /// Display P3: the sRGB transfer function and the DCI-P3 primaries.
+    DisplayP3 = 1,
+
+    /// Adobe RGB (1998): a gamma of `563 / 256` and wide green primary.
+    AdobeRGB = 2,
+
+    /// Rec. 2020: the Rec. 709 transfer function and the Rec. 2020
+    /// primaries.
+    Rec2020 = 3,
+}
+
+/// The matrices that convert linear colours in each [`ColourSpace`] to
+/// linear Rec. 709.
+const TO_CANONICAL: [[[f32; 3]; 3]; 4] = [
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    [[1.2249401, -0.2249404, 0.0], [-0.0420569, 1.0420571, 0.0], [-0.0196376, -0.0786361, 1.0982735]],
+    [[1.3983557, -0.3983557, 0.0], [0.0, 1.0, 0.0], [0.0, -0.0429289, 1.0429289]],
+    [[1.660_491, -0.5876411, -0.0728499], [-0.1245505, 1.1328999, -0.0083494], [-0.0181508, -0.1005789, 1.1187297]],
+];
+
+/// Returns the inverse of a 3×3 matrix.
+fn invert(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (i1, i2) = ((i + 1) % 3, (i + 2) % 3);
+        let (j1, j2) = ((j + 1) % 3, (j + 2) % 3);
+        m[i1][j1] * m[i2][j2] - m[i1][j2] * m[i2][j1]
+    };
+    let det: f32 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(j, i) / det))
+}
+
+impl ColourSpace {
+    /// Returns the `ColourSpace` whose discriminant is `x`.
+    pub fn from_u8(x: u8) -> crate::Result<Self> {
+        Ok(match x {
+            0 => ColourSpace::SRGB,
+            1 => ColourSpace::DisplayP3,
+            2 => ColourSpace::AdobeRGB,
+            3 => ColourSpace::Rec2020,
+            _ => Err(crate::Error("Unknown colour space"))?,
+        })
+    }
+
+    /// Converts a gamma-corrected component in the range `0.0` to `1.0` to
+    /// linear light.
+    pub fn expand_gamma(self, x: f32) -> f32 {
+        match self {
+            ColourSpace::SRGB | ColourSpace::DisplayP3 => colcon::expand_gamma(x),
+            ColourSpace::AdobeRGB => x.max(0.0).powf(563.0 / 256.0),
+            ColourSpace::Rec2020 => if x < 0.081 { x / 4.5 } else { ((x + 0.099) / 1.099).powf(1.0 / 0.45) },
+        }
+    }
+
+    /// The inverse of `expand_gamma()`.
+    pub fn correct_gamma(self, x: f32) -> f32 {
+        match self {
+            ColourSpace::SRGB | ColourSpace::DisplayP3 => colcon::correct_gamma(x),
+            ColourSpace::AdobeRGB => x.max(0.0).powf(256.0 / 563.0),
+            ColourSpace::Rec2020 => if x < 0.018 { x * 4.5 } else { 1.099 * x.powf(0.45) - 0.099 },
+        }
+    }
+
+    /// Converts the colour channels of `pixels` from linear light in `self`
+    /// to linear Rec. 709. `C` must have at least three channels, and the
+    /// first three must be red, green and blue.
+    pub fn to_canonical<C: Channels>(self, pixels: &PixelArray<C>) -> PixelArray<C> {
+        transform(pixels, &TO_CANONICAL[self as usize])
+    }
+
+    /// The inverse of `to_canonical()`.
+    pub fn from_canonical<C: Channels>(self, pixels: &PixelArray<C>) -> PixelArray<C> {
+        transform(pixels, &invert(&TO_CANONICAL[self as usize]))
+    }
+}
+
+impl std::str::FromStr for ColourSpace {
+    type Err = crate::Error;
+
+    /// Parses one of "srgb", "display-p3", "adobe-rgb" or "rec2020".
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "srgb" => ColourSpace::SRGB,
+            "display-p3" => ColourSpace::DisplayP3,
+            "adobe-rgb" => ColourSpace::AdobeRGB,
+            "rec2020" => ColourSpace::Rec2020,
+            _ => Err(crate::Error("Unknown colour space"))?,
+        })
+    }
+}
+
+/// Multiplies the first three channels of every pixel of `pixels` by
+/// `matrix`. Any other channels are unchanged.
+fn transform<C: Channels>(pixels: &PixelArray<C>, matrix: &[[f32; 3]; 3]) -> PixelArray<C> {
+    assert!(C::NUM_CHANNELS >= 3);
+    let rgb: PixelArray<RGB> = map_pixels(&PixelArray::<RGB>::from_channels(
+        &C::ALL[..3].iter().map(|&c| pixels.channel(c)).collect::<Vec<_>>(),
+    ), |v| matrix.map(|row| (0..3).map(|j| row[j] * v[j]).sum()));
+    PixelArray(Array::from_fn((pixels.pixel_size(), ()), |(yx, c): (Grid, C)| {
+        let i = StaticIndex::to_usize(c);
+        if i < 3 { rgb.at((yx, RGB::ALL[i])) } else { pixels.at((yx, c)) }
+    }))
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A selection of colours, including the corners of the RGB cube.
+    const COLOURS: [[f32; 3]; 10] = [
+        [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0],
+        [1.0, 1.0, 0.0], [1.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 1.0, 1.0],
+        [0.5, 0.25, 0.125], [0.01, 0.9, 0.3],
+    ];
+
+    #[test]
+    fn round_trip() {
+        for rgb in COLOURS {
+            let rgb2 = ycc_to_rgb(rgb_to_ycc(rgb));
+            for i in 0..3 { assert!((rgb[i] - rgb2[i]).abs() < 1e-6, "{:?} {:?}", rgb, rgb2); }
+        }
+    }
+
+    #[test]
+    fn range() {
+        for rgb in COLOURS {
+            let [y, cb, cr] = rgb_to_ycc(rgb);
+            assert!((-1e-6..=1.0 + 1e-6).contains(&y));
+            assert!((-0.5 - 1e-6..=0.5 + 1e-6).contains(&cb));
+            assert!((-0.5 - 1e-6..=0.5 + 1e-6).contains(&cr));
+        }
+        for grey in [0.0, 0.3, 1.0] {
+            let [y, cb, cr] = rgb_to_ycc([grey; 3]);
+            assert!((y - grey).abs() < 1e-6);
+            assert!(cb.abs() < 1e-6 && cr.abs() < 1e-6);
+        }
+        assert!((rgb_to_ycc([0.0, 0.0, 1.0])[1] - 0.5).abs() < 1e-6);
+        assert!((rgb_to_ycc([1.0, 0.0, 0.0])[2] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pixel_array() {
+        let pixels = PixelArray::<RGB>(Array::from_fn(((3, 4), ()), |((y, x), c): (Grid, RGB)| {
+            (y * 4 + x) as f32 * 0.05 + c.to_usize() as f32 * 0.1
+        }));
+        let ycc = to_ycc(&pixels);
+        assert_eq!(ycc.size(), ((3, 4), ()));
+        let yx = (2, 1);
+        let rgb = [RGB::Red, RGB::Green, RGB::Blue].map(|c| pixels.at((yx, c)));
+        assert!((ycc.at((yx, YCC::Luma)) - luma(rgb[0], rgb[1], rgb[2])).abs() < 1e-6);
+        let pixels2 = from_ycc(&ycc);
+        (&pixels.0).zip(&pixels2.0).each(|(x, y)| { assert!((x - y).abs() < 1e-6); });
+    }
+
+    #[test]
+    fn colour_spaces() {
+        for space in [ColourSpace::SRGB, ColourSpace::DisplayP3, ColourSpace::AdobeRGB, ColourSpace::Rec2020] {
+            assert_eq!(ColourSpace::from_u8(space as u8).unwrap(), space);
+            for x in [0.0, 0.01, 0.5, 1.0] {
+                let y = space.correct_gamma(space.expand_gamma(x));
+                assert!((x - y).abs() < 1e-4, "{:?} {} {}", space, x, y);
+            }
+            // White is white in every space.
+            let m = &TO_CANONICAL[space as usize];
+            for row in m { assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-4); }
+            let pixels = PixelArray::<RGB>(Array::from_fn(((1, 2), ()), |((_, x), c): (Grid, RGB)| {
+                0.2 + 0.3 * x as f32 + 0.1 * c.to_usize() as f32
+            }));
+            let pixels2 = space.from_canonical(&space.to_canonical(&pixels));
+            (&pixels.0).zip(&pixels2.0).each(|(x, y)| { assert!((x - y).abs() < 1e-5); });
+        }
+        // Pure P3 green is outside the sRGB gamut.
+        let green = TO_CANONICAL[ColourSpace::DisplayP3 as usize].map(|row| row[1]);
+        assert!(green[0] < 0.0 && green[1] > 1.0);
+        assert!("display-p3".parse::<ColourSpace>().is_ok());
+    }
+}
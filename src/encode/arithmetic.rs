@@ -1,6 +1,6 @@
 use std::cmp::{min, max};
 
-use super::{BitString, BitIter};
+use super::{BitSink, BitString};
 
 const SCALE: u64 = 1 << 32;
 const HALF: u64 = SCALE / 2;
@@ -25,7 +25,7 @@ impl Split {
     fn new_inner(p1: u32) -> Self {
         let p1 = min(p1, !3); // Small enough that `State::below` changes.
         let p1 = max(p1, 4); // Large enough that `State::above` changes.
-        Self {p1: p1}
+        Self {p1}
     }
 
     /// Constructs a `Split` given the probability of `true`.
@@ -39,6 +39,12 @@ impl Split {
         let total = f0.checked_add(f1).expect("Total must be less than 1<<64");
         Self::new(f1 as f64 / total as f64)
     }
+
+    /// Returns the number of bits needed to code `data` using `self`.
+    pub fn cost(self, data: bool) -> f64 {
+        let p1 = self.p1 as f64 / SCALE as f64;
+        -(if data { p1 } else { 1.0 - p1 }).log2()
+    }
 }
 
 /// An equal [`Split`]: `true` and `false` have equal probability.
@@ -46,6 +52,54 @@ pub const FAIR: Split = Split {p1: HALF as u32};
 
 // ----------------------------------------------------------------------------
 
+/// The maximum weight of the observations remembered by an [`AdaptiveSplit`].
+const LIMIT: u32 = 1 << 10;
+
+/// A [`Split`] that learns from the bits it is used to code.
+///
+/// The probability of `true` is the weighted mean of an initial estimate and
+/// the observed bits. Once the total weight reaches a limit, older
+/// observations are forgotten exponentially.
+///
+/// [`Writer::write_adaptive()`] and [`Reader::read_adaptive()`] call
+/// [`update()`] with the same arguments, so the encoder and decoder stay in
+/// step.
+///
+/// [`update()`]: Self::update
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveSplit {
+    /// `SCALE` times the probability of `true`.
+    p1: u32,
+    /// The total weight of the initial estimate and the observations.
+    weight: u32,
+}
+
+impl AdaptiveSplit {
+    /// Constructs an `AdaptiveSplit` whose initial estimate is `split` and is
+    /// worth `weight` observations (at least one).
+    pub fn new(split: Split, weight: u32) -> Self {
+        Self {p1: split.p1, weight: weight.clamp(1, LIMIT)}
+    }
+
+    /// Returns the current estimate.
+    pub fn split(&self) -> Split { Split::new_inner(self.p1) }
+
+    /// Adjust the estimate after observing `data`.
+    pub fn update(&mut self, data: bool) {
+        self.weight = min(self.weight + 1, LIMIT);
+        let target = if data { SCALE as i64 } else { 0 };
+        let p1 = self.p1 as i64;
+        self.p1 = (p1 + (target - p1) / self.weight as i64) as u32;
+    }
+}
+
+impl Default for AdaptiveSplit {
+    /// An `AdaptiveSplit` with no prior knowledge.
+    fn default() -> Self { Self::new(FAIR, 1) }
+}
+
+// ----------------------------------------------------------------------------
+
 /// Represents an interval inside [0, 1].
 #[derive(Default, Debug, Copy, Clone, Hash, PartialEq, Eq)]
 struct Interval {
@@ -114,15 +168,21 @@ const UPPER: Interval = Interval {below: HALF as u32, above: 0};
 // ----------------------------------------------------------------------------
 
 /// Read arithmetic-encoded data.
+///
+/// The bits are read from `I`, which can be a [`BitIter`] or, to decode data
+/// without holding all of it in memory, a [`ByteReader`].
+///
+/// [`BitIter`]: super::BitIter
+/// [`ByteReader`]: super::ByteReader
 #[derive(Debug)]
-pub struct Reader<'a> {
-    inner: BitIter<'a>,
+pub struct Reader<I> {
+    inner: I,
     unfair: Interval,
     fair: Interval,
 }
 
-impl<'a> Reader<'a> {
-    pub fn new(inner: BitIter<'a>) -> Self {
+impl<I: Iterator<Item=bool>> Reader<I> {
+    pub fn new(inner: I) -> Self {
         Self {inner, unfair: WHOLE, fair: WHOLE}
     }
 
@@ -160,8 +220,15 @@ impl<'a> Reader<'a> {
         Some(data)
     }
 
+    /// Read one bit using `model`, then update `model`.
+    pub fn read_adaptive(&mut self, model: &mut AdaptiveSplit) -> Option<bool> {
+        let data = self.read(model.split())?;
+        model.update(data);
+        Some(data)
+    }
+
     /// Skip padding.
-    pub fn close(self) -> BitIter<'a> {
+    pub fn close(self) -> I {
         assert!(self.unfair.contains(self.fair));
         self.inner
     }
@@ -170,15 +237,20 @@ impl<'a> Reader<'a> {
 // ----------------------------------------------------------------------------
 
 /// Write arithmetic-encoded data.
+///
+/// The bits are written to `S`, which can be a [`BitString`] or, to write
+/// data without holding all of it in memory, a [`ByteWriter`].
+///
+/// [`ByteWriter`]: super::ByteWriter
 #[derive(Debug)]
-pub struct Writer {
-    inner: BitString,
+pub struct Writer<S=BitString> {
+    inner: S,
     unfair: Interval,
     middle_count: usize,
 }
 
-impl Writer {
-    pub fn new(inner: BitString) -> Self {
+impl<S: BitSink> Writer<S> {
+    pub fn new(inner: S) -> Self {
         Self {inner, unfair: WHOLE, middle_count: 0}
     }
 
@@ -210,8 +282,14 @@ impl Writer {
         while self.grow(MIDDLE) { self.middle_count += 1; }
     }
 
+    /// Write `data` using `model`, then update `model`.
+    pub fn write_adaptive(&mut self, model: &mut AdaptiveSplit, data: bool) {
+        self.write(model.split(), data);
+        model.update(data);
+    }
+
     /// Pad as necessary to write all data.
-    pub fn close(mut self) -> BitString {
+    pub fn close(mut self) -> S {
         if self.unfair.above > self.unfair.below {
             self.inner_write(false);
             if self.unfair.below > 0 {
@@ -281,4 +359,56 @@ mod tests {
 
     #[test]
     fn very_unfair() { check(Split::new_ratio(6, 1)); }
+
+    #[test]
+    fn cost() {
+        assert_eq!(FAIR.cost(false), 1.0);
+        assert_eq!(FAIR.cost(true), 1.0);
+        let split = Split::new_ratio(3, 1);
+        assert!((split.cost(false) - (4.0f64 / 3.0).log2()).abs() < 1e-9);
+        assert!((split.cost(true) - 2.0).abs() < 1e-9);
+    }
+
+    /// Generate `n` pseudo-random bits, each `true` with probability `p1`.
+    fn random_bits(n: usize, p1: f64) -> Vec<bool> {
+        let mut seed: u32 = 1;
+        (0..n).map(|_| {
+            seed = seed.wrapping_mul(3141592653);
+            seed = seed.wrapping_add(2718281845);
+            (seed as f64) < p1 * (1u64 << 32) as f64
+        }).collect()
+    }
+
+    #[test]
+    fn adaptive() {
+        for p1 in [0.5, 0.2, 0.05, 0.01] {
+            let bits = random_bits(100000, p1);
+            let mut w = Writer::new(BitString::default());
+            let mut model = AdaptiveSplit::default();
+            for &bit in &bits { w.write_adaptive(&mut model, bit); }
+            let bs = w.close();
+            // Compare with the empirical entropy.
+            let n1 = bits.iter().filter(|&&bit| bit).count() as f64;
+            let n0 = bits.len() as f64 - n1;
+            let total = bits.len() as f64;
+            let entropy = n0 * (total / n0).log2() + n1 * (total / n1).log2();
+            println!("p1 = {}: {} bits, entropy = {}", p1, bs.len(), entropy);
+            assert!((bs.len() as f64) < entropy * 1.01 + 64.0);
+            // Check that the data can be decoded.
+            let mut r = Reader::new(bs.iter());
+            let mut model = AdaptiveSplit::default();
+            for &bit in &bits { assert_eq!(r.read_adaptive(&mut model), Some(bit)); }
+            assert!(r.close().next().is_none());
+        }
+    }
+
+    #[test]
+    fn adaptive_limit() {
+        // Old observations are forgotten.
+        let mut model = AdaptiveSplit::new(FAIR, 1);
+        for _ in 0..100000 { model.update(true); }
+        assert!(model.split().p1 as u64 > SCALE - 2 * LIMIT as u64);
+        for _ in 0..100000 { model.update(false); }
+        assert!((model.split().p1 as u64) < 2 * LIMIT as u64);
+    }
 }
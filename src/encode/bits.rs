@@ -1,3 +1,11 @@
+/// Something to which bits can be appended, such as a [`BitString`].
+pub trait BitSink {
+    /// Append one bit.
+    fn push(&mut self, bit: bool);
+}
+
+// ----------------------------------------------------------------------------
+
 #[derive(Default, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct BitString {
     /// Complete 64-bit words (little-endian).
@@ -14,6 +22,9 @@ impl BitString {
     /// The number of bits in this `BitString`.
     pub fn len(&self) -> usize { 64 * self.words.len() + (self.bit as usize) }
 
+    /// Returns `true` if this `BitString` contains no bits.
+    pub fn is_empty(&self) -> bool { self.words.is_empty() && self.bit == 0 }
+
     /// Append one bit.
     pub fn push(&mut self, bit: bool) {
         self.last_word |= (bit as u64) << self.bit;
@@ -42,7 +53,42 @@ impl BitString {
     }
 
     /// Returns an [`Iterator`] through the bits of this `BitString`.
-    pub fn iter(&self) -> BitIter { self.into_iter() }
+    pub fn iter(&self) -> BitIter<'_> { self.into_iter() }
+
+    /// Returns the bits of this `BitString` packed into bytes, least
+    /// significant bit first. The last byte is padded with zeros.
+    ///
+    /// The length is not recorded; see [`from_bytes()`].
+    ///
+    /// [`from_bytes()`]: Self::from_bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.extend_from_slice(&self.last_word.to_le_bytes()[..(self.bit as usize).div_ceil(8)]);
+        bytes
+    }
+
+    /// Constructs a `BitString` from the first `len` bits of `bytes`, in the
+    /// format of [`to_bytes()`]. Padding bits are ignored.
+    ///
+    /// Panics if `bytes` contains fewer than `len` bits.
+    ///
+    /// [`to_bytes()`]: Self::to_bytes
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Self {
+        assert!(len <= 8 * bytes.len(), "Not enough bytes");
+        let (whole, rest) = bytes[..len.div_ceil(8)].split_at(8 * (len / 64));
+        let words = whole.chunks_exact(8).map(
+            |chunk| u64::from_le_bytes(chunk.try_into().unwrap())
+        ).collect();
+        let mut buffer = [0; 8];
+        buffer[..rest.len()].copy_from_slice(rest);
+        let bit = (len % 64) as u8;
+        let last_word = u64::from_le_bytes(buffer) & ((1 << bit) - 1);
+        Self {words, last_word, bit}
+    }
+}
+
+impl BitSink for BitString {
+    fn push(&mut self, bit: bool) { BitString::push(self, bit); }
 }
 
 impl<'a> IntoIterator for &'a BitString {
@@ -125,4 +171,19 @@ mod tests {
         assert!(bs.pop().is_none());
         assert!(bv.pop().is_none());
     }
+
+    #[test]
+    pub fn bytes() {
+        for len in [0usize, 1, 7, 8, 9, 63, 64, 65, 200] {
+            let mut bs = BitString::default();
+            for i in 0..len { bs.push(i % 3 == 1); }
+            let bytes = bs.to_bytes();
+            assert_eq!(bytes.len(), len.div_ceil(8));
+            assert_eq!(BitString::from_bytes(&bytes, len), bs);
+        }
+        // Padding bits are ignored.
+        let bs = BitString::from_bytes(&[0xFF, 0xFF], 9);
+        assert_eq!(bs.len(), 9);
+        assert_eq!(bs.to_bytes(), [0xFF, 0x01]);
+    }
 }
@@ -0,0 +1,148 @@
+use std::io::{self, Read, Write};
+
+use super::{BitSink};
+
+/// A [`BitSink`] that packs bits into bytes and writes them to a [`Write`].
+///
+/// Bits are packed least significant bit first, in the same format as
+/// [`BitString::to_bytes()`]. Errors are remembered and returned by
+/// [`finish()`].
+///
+/// [`BitString::to_bytes()`]: super::BitString::to_bytes
+/// [`finish()`]: Self::finish
+#[derive(Debug)]
+pub struct ByteWriter<W: Write> {
+    inner: W,
+
+    /// The incomplete last byte. Unused bits are zero.
+    byte: u8,
+
+    /// The number of bits used in `byte` (0 to 7).
+    bit: u8,
+
+    /// The first error returned by `inner`, if any.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> ByteWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {inner, byte: 0, bit: 0, error: None}
+    }
+
+    /// Pad the last byte with zeros and write it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.bit > 0 { self.write_byte(); }
+        if let Some(e) = self.error { return Err(e); }
+        Ok(self.inner)
+    }
+
+    /// Write `byte` and reset it.
+    fn write_byte(&mut self) {
+        if self.error.is_none() {
+            if let Err(e) = self.inner.write_all(&[self.byte]) { self.error = Some(e); }
+        }
+        self.byte = 0;
+        self.bit = 0;
+    }
+}
+
+impl<W: Write> BitSink for ByteWriter<W> {
+    fn push(&mut self, bit: bool) {
+        self.byte |= (bit as u8) << self.bit;
+        self.bit += 1;
+        if self.bit == 8 { self.write_byte(); }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// An [`Iterator`] through the bits of the bytes read from a [`Read`].
+///
+/// This is the inverse of [`ByteWriter`]. The iterator ends at the end of the
+/// data, or at the first error. Errors are remembered and returned by
+/// [`finish()`].
+///
+/// [`finish()`]: Self::finish
+#[derive(Debug)]
+pub struct ByteReader<R: Read> {
+    inner: R,
+
+    /// The unused bits of the last byte read, in its least significant bits.
+    byte: u8,
+
+    /// The number of unused bits in `byte` (0 to 7).
+    bit: u8,
+
+    /// The first error returned by `inner`, if any.
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ByteReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {inner, byte: 0, bit: 0, error: None}
+    }
+
+    /// Discard the unused bits of the last byte read.
+    pub fn finish(self) -> io::Result<R> {
+        if let Some(e) = self.error { return Err(e); }
+        Ok(self.inner)
+    }
+}
+
+impl<R: Read> Iterator for ByteReader<R> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bit == 0 {
+            if self.error.is_some() { return None; }
+            let mut buffer = [0];
+            match self.inner.read_exact(&mut buffer) {
+                Ok(()) => {},
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => { return None; },
+                Err(e) => { self.error = Some(e); return None; },
+            }
+            self.byte = buffer[0];
+            self.bit = 8;
+        }
+        let ret = self.byte & 1 != 0;
+        self.byte >>= 1;
+        self.bit -= 1;
+        Some(ret)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{BitString, Split, Reader, Writer};
+
+    #[test]
+    fn round_trip() {
+        let split = Split::new_ratio(5, 2);
+        let bits: Vec<bool> = (0..1000).map(|i| i % 7 == 3).collect();
+        // Write the same data to a `BitString` and to a `Vec<u8>`.
+        let mut w = Writer::new(BitString::default());
+        for &bit in &bits { w.write(split, bit); }
+        let bs = w.close();
+        let mut w = Writer::new(ByteWriter::new(Vec::new()));
+        for &bit in &bits { w.write(split, bit); }
+        let mut bytes = w.close().finish().unwrap();
+        assert_eq!(bytes, bs.to_bytes());
+        // Read it back, followed by some other data.
+        bytes.push(42);
+        let mut r = Reader::new(ByteReader::new(bytes.as_slice()));
+        for &bit in &bits { assert_eq!(r.read(split), Some(bit)); }
+        let rest = r.close().finish().unwrap();
+        assert_eq!(rest, [42]);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut r = ByteReader::new([0x81u8].as_slice());
+        let bits: Vec<bool> = (&mut r).collect();
+        assert_eq!(bits, [true, false, false, false, false, false, false, true]);
+        assert!(r.finish().is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+use multidimension::{Size, View, Array};
+
+use crate::{Error, Result, Grid};
+use super::{FAIR, AdaptiveSplit, SymbolModel, BitSink, Reader, Writer};
+
+/// The number of magnitude classes. Class `0` is zero, and class `k > 0`
+/// contains the magnitudes from `1 << (k - 1)` to `(1 << k) - 1`.
+const NUM_CLASSES: usize = 33;
+
+/// The initial relative frequencies of the first few magnitude classes.
+const CLASS_COUNTS: [u64; 8] = [16, 16, 12, 8, 5, 3, 2, 1];
+
+/// The weight of [`CLASS_COUNTS`] relative to the statistics of the image
+/// being coded.
+const PRIOR_WEIGHT: u32 = 4;
+
+/// The number of distinct [`LowModel::context()`]s.
+const NUM_CONTEXTS: usize = 3;
+
+/// Returns the magnitude class of `q`.
+fn class(q: i32) -> usize { (32 - q.unsigned_abs().leading_zeros()) as usize }
+
+// ----------------------------------------------------------------------------
+
+/// The probability models used to code the low-frequency image of one
+/// channel, as quantised by [`low_to_digital()`].
+///
+/// Each integer is coded as its magnitude class, then its sign, then the bits
+/// of its magnitude below the leading one, which are assumed to be random.
+/// The magnitude class is coded using a model selected by the classes of the
+/// integers above and to the left, which must therefore be coded in raster
+/// order. The models adapt to the statistics of the image being coded.
+///
+/// [`low_to_digital()`]: crate::quantize::low_to_digital
+#[derive(Debug, Clone)]
+pub struct LowModel {
+    /// For each context, the probability of each magnitude class.
+    pub class: Box<[SymbolModel]>,
+
+    /// The probability that a non-zero integer is negative.
+    pub sign: AdaptiveSplit,
+}
+
+impl Default for LowModel {
+    fn default() -> Self {
+        let mut counts = [0; NUM_CLASSES];
+        counts[..CLASS_COUNTS.len()].copy_from_slice(&CLASS_COUNTS);
+        Self {
+            class: vec![SymbolModel::new(&counts, PRIOR_WEIGHT); NUM_CONTEXTS].into(),
+            sign: AdaptiveSplit::default(),
+        }
+    }
+}
+
+impl LowModel {
+    /// Returns the context of the integer at `(y, x)`.
+    fn context(digital: &Array<Grid, i32>, (y, x): Grid) -> usize {
+        let above = if y > 0 { class(digital[(y - 1, x)]) } else { 0 };
+        let left = if x > 0 { class(digital[(y, x - 1)]) } else { 0 };
+        match above + left {
+            0..=1 => 0,
+            2..=4 => 1,
+            _ => 2,
+        }
+    }
+
+    /// Write `digital`.
+    pub fn write_low(&mut self, w: &mut Writer<impl BitSink>, digital: &Array<Grid, i32>) {
+        digital.size().each(|yx| {
+            let q = digital[yx];
+            let class = class(q);
+            w.write_symbol(&mut self.class[Self::context(digital, yx)], class);
+            if class == 0 { return; }
+            w.write_adaptive(&mut self.sign, q < 0);
+            let magnitude = q.unsigned_abs();
+            for i in (0..class - 1).rev() { w.write(FAIR, (magnitude >> i) & 1 != 0); }
+        });
+    }
+
+    /// Read an `Array` of `size` written by `write_low()`.
+    ///
+    /// Fails if `r` ends early, or if an integer does not fit in an `i32`.
+    pub fn read_low(&mut self, r: &mut Reader<impl Iterator<Item=bool>>, size: Grid) -> Result<Array<Grid, i32>> {
+        let truncated = || Error("Truncated file");
+        let mut digital = Array::from_fn(size, |_| 0);
+        for y in 0..size.0 {
+            for x in 0..size.1 {
+                let yx = (y, x);
+                let class = r.read_symbol(&mut self.class[Self::context(&digital, yx)]).ok_or_else(truncated)?;
+                if class == 0 { continue; }
+                let is_negative = r.read_adaptive(&mut self.sign).ok_or_else(truncated)?;
+                let mut magnitude: u32 = 1;
+                for _ in 0..class - 1 { magnitude = (magnitude << 1) | r.read(FAIR).ok_or_else(truncated)? as u32; }
+                let q = if is_negative { (magnitude as i64).wrapping_neg() } else { magnitude as i64 };
+                digital[yx] = i32::try_from(q).or(Err(Error("Corrupt low-frequency data")))?;
+            }
+        }
+        Ok(digital)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::encode::{BitString};
+
+    #[test]
+    fn round_trip() {
+        let digital = Array::from_fn((4, 5), |(y, x)| [0, 1, -1, 7, -300, i32::MAX, i32::MIN][(y * 5 + x) % 7]);
+        let mut model = LowModel::default();
+        let mut w = Writer::new(BitString::default());
+        model.write_low(&mut w, &digital);
+        let bits = w.close();
+        let mut model = LowModel::default();
+        let mut r = Reader::new(bits.iter());
+        let digital2 = model.read_low(&mut r, digital.size()).unwrap();
+        (&digital).zip(&digital2).each(|(a, b)| assert_eq!(a, b));
+    }
+
+    #[test]
+    fn corrupt() {
+        // `i32::MAX + 1` is in the largest magnitude class, but is not an `i32`.
+        let mut model = LowModel::default();
+        let mut w = Writer::new(BitString::default());
+        w.write_symbol(&mut model.class[0], NUM_CLASSES - 1);
+        w.write_adaptive(&mut model.sign, false);
+        for _ in 0..NUM_CLASSES - 2 { w.write(FAIR, false); }
+        let bits = w.close();
+        let mut model = LowModel::default();
+        let error = model.read_low(&mut Reader::new(bits.iter()), (1, 1)).unwrap_err();
+        assert_eq!(error.to_string(), "Corrupt low-frequency data");
+        let error = model.read_low(&mut Reader::new(bits.iter().take(4)), (2, 2)).unwrap_err();
+        assert_eq!(error.to_string(), "Truncated file");
+    }
+}
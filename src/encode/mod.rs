@@ -1,5 +1,17 @@
 mod bits;
-pub use bits::{BitString, BitIter};
+pub use bits::{BitSink, BitString, BitIter};
+
+mod bytes;
+pub use bytes::{ByteWriter, ByteReader};
 
 mod arithmetic;
-pub use arithmetic::{Split, FAIR, Reader, Writer};
+pub use arithmetic::{Split, FAIR, AdaptiveSplit, Reader, Writer};
+
+mod symbol;
+pub use symbol::{SymbolModel};
+
+mod tree;
+pub use tree::{MAX_LENGTH, Context, TreeModel};
+
+mod low;
+pub use low::{LowModel};
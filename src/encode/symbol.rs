@@ -0,0 +1,167 @@
+use std::cmp::{Reverse};
+use std::collections::{BinaryHeap};
+
+use super::{Split, AdaptiveSplit, BitSink, Reader, Writer};
+
+/// A child of a [`Node`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Child {
+    /// A symbol.
+    Symbol(usize),
+    /// Another `Node`.
+    Node(usize),
+}
+
+/// An internal node of a [`SymbolModel`].
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    /// The probability of choosing `children[1]`.
+    split: AdaptiveSplit,
+    /// The subtrees selected by `false` and `true`.
+    children: [Child; 2],
+}
+
+/// The indices of the [`Node`]s on the way from the root to a symbol, and the
+/// bit that chooses the next step at each one.
+type Route = Box<[(usize, bool)]>;
+
+// ----------------------------------------------------------------------------
+
+/// Represents a model of the relative probabilities of the symbols `0` to
+/// `n - 1`.
+///
+/// A symbol is coded as a sequence of bits, each of which chooses between the
+/// two children of a node of a binary tree. The shape of the tree is
+/// constructed from a frequency table using Huffman's algorithm, so that
+/// common symbols need few bits. Each node has an [`AdaptiveSplit`], so that
+/// the model learns the statistics of the data being coded.
+#[derive(Debug, Clone)]
+pub struct SymbolModel {
+    /// The internal nodes. The root is the last one.
+    nodes: Box<[Node]>,
+    /// For each symbol, its `Route`.
+    routes: Box<[Route]>,
+}
+
+impl SymbolModel {
+    /// Constructs a `SymbolModel` given the frequency of each symbol.
+    ///
+    /// Every frequency is incremented, so that no symbol is impossible. The
+    /// frequencies are worth `weight` observations (see
+    /// [`AdaptiveSplit::new()`]).
+    pub fn new(counts: &[u64], weight: u32) -> Self {
+        assert!(!counts.is_empty(), "Must have at least one symbol");
+        let n = counts.len();
+        // Huffman's algorithm. Ties are broken by the order of creation.
+        let mut nodes = Vec::with_capacity(n - 1);
+        let mut heap: BinaryHeap<Reverse<(u64, usize, Child)>> = counts.iter().enumerate().map(
+            |(i, &c)| Reverse((c + 1, i, Child::Symbol(i)))
+        ).collect();
+        while let Some(Reverse((c0, _, child0))) = heap.pop() {
+            let Some(Reverse((c1, _, child1))) = heap.pop() else { break; };
+            heap.push(Reverse((c0 + c1, n + nodes.len(), Child::Node(nodes.len()))));
+            let split = AdaptiveSplit::new(Split::new_ratio(c0, c1), weight);
+            nodes.push(Node {split, children: [child0, child1]});
+        }
+        // Find the route to each symbol.
+        let mut routes: Vec<Route> = vec![Box::new([]); n];
+        let mut stack = Vec::new();
+        if !nodes.is_empty() { stack.push((nodes.len() - 1, Vec::new())); }
+        while let Some((index, route)) = stack.pop() {
+            for (bit, &child) in [false, true].into_iter().zip(&nodes[index].children) {
+                let mut route = route.clone();
+                route.push((index, bit));
+                match child {
+                    Child::Symbol(symbol) => { routes[symbol] = route.into(); },
+                    Child::Node(child) => { stack.push((child, route)); },
+                }
+            }
+        }
+        Self {nodes: nodes.into(), routes: routes.into()}
+    }
+
+    /// Returns the number of symbols.
+    pub fn num_symbols(&self) -> usize { self.routes.len() }
+
+    /// Returns `true` if there is only one symbol, which costs nothing.
+    pub fn is_trivial(&self) -> bool { self.nodes.is_empty() }
+
+    /// Returns the number of bits needed to code `symbol` using `self`.
+    pub fn cost(&self, symbol: usize) -> f64 {
+        self.routes[symbol].iter().map(|&(index, bit)| self.nodes[index].split.split().cost(bit)).sum()
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+impl<S: BitSink> Writer<S> {
+    /// Write `symbol` using `model`, then update `model`.
+    pub fn write_symbol(&mut self, model: &mut SymbolModel, symbol: usize) {
+        assert!(symbol < model.num_symbols(), "Symbol is out of range");
+        for &(index, bit) in model.routes[symbol].iter() {
+            self.write_adaptive(&mut model.nodes[index].split, bit);
+        }
+    }
+}
+
+impl<I: Iterator<Item=bool>> Reader<I> {
+    /// Read a symbol using `model`, then update `model`.
+    pub fn read_symbol(&mut self, model: &mut SymbolModel) -> Option<usize> {
+        if model.is_trivial() { return Some(0); }
+        let mut index = model.nodes.len() - 1;
+        loop {
+            let node = &mut model.nodes[index];
+            let bit = self.read_adaptive(&mut node.split)?;
+            match node.children[bit as usize] {
+                Child::Symbol(symbol) => { return Some(symbol); },
+                Child::Node(child) => { index = child; },
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap};
+
+    use super::*;
+    use crate::encode::{BitString};
+
+    #[test]
+    fn shape() {
+        let model = SymbolModel::new(&[99, 49, 24, 24], 1);
+        let lengths: Vec<usize> = model.routes.iter().map(|r| r.len()).collect();
+        assert_eq!(lengths, [1, 2, 3, 3]);
+        assert!((model.cost(0) - 1.0).abs() < 1e-6);
+        assert!((model.cost(3) - 3.0).abs() < 1e-6);
+        let model = SymbolModel::new(&[7], 1);
+        assert!(model.is_trivial());
+        assert_eq!(model.cost(0), 0.0);
+    }
+
+    #[test]
+    fn round_trip() {
+        // Build a model from a histogram, in the style of `bcc-stats`.
+        let mut histogram: HashMap<char, usize> = HashMap::new();
+        let text = "the quick brown fox jumps over the lazy dog";
+        for c in text.chars() { *histogram.entry(c).or_insert(0) += 1; }
+        let alphabet: Vec<char> = ('a'..='z').chain([' ']).collect();
+        let counts: Vec<u64> = alphabet.iter().map(
+            |c| *histogram.get(c).unwrap_or(&0) as u64
+        ).collect();
+        let symbols: Vec<usize> = text.chars().map(
+            |c| alphabet.iter().position(|&a| a == c).unwrap()
+        ).collect();
+        let mut w = Writer::new(BitString::default());
+        let mut model = SymbolModel::new(&counts, 4);
+        for &s in &symbols { w.write_symbol(&mut model, s); }
+        let bs = w.close();
+        assert!(bs.len() < 5 * symbols.len());
+        let mut r = Reader::new(bs.iter());
+        let mut model = SymbolModel::new(&counts, 4);
+        for &s in &symbols { assert_eq!(r.read_symbol(&mut model), Some(s)); }
+        assert!(r.close().next().is_none());
+    }
+}
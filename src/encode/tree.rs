@@ -0,0 +1,367 @@
+use multidimension::{View, Array};
+
+use crate::{Grid, Quad, Tree, Position};
+use crate::quantize::{ShiftedBCC, Symmetry, ALL_SYMMETRIES, Residual, ALL_RESIDUALS, Chain, Rate};
+use super::{Split, AdaptiveSplit, SymbolModel, BitSink, Reader, Writer};
+
+/// The length of the longest [`Chain`] that can be coded.
+pub const MAX_LENGTH: usize = 15;
+
+// The following counts were measured by `bcc-stats` (see `bcc-stats.txt`).
+
+/// The number of [`Tree::Leaf`]s and [`Tree::Branch`]es.
+const LEAF_BRANCH_COUNTS: [u64; 2] = [251038887, 83321129];
+
+/// For each length, the number of [`Chain`]s of that length.
+const LENGTH_COUNTS: [u64; MAX_LENGTH + 1] = [
+    46770698, 20768985, 8780646, 3819785, 1924626, 875206, 280226, 78694,
+    18698, 3306, 259, 0, 0, 0, 0, 0,
+];
+
+/// The number of [`Chain`]s ending at each fixed point, after normalising
+/// the [`Symmetry`].
+const FIXED_POINT_COUNTS: [u64; 2] = [8490988, 74830141];
+
+/// For each fixed point, the number of non-empty [`Chain`]s with each most
+/// significant [`Residual`].
+const LAST_COUNTS: [[u64; 8]; 2] = [
+    [0, 67144, 67628, 1525626, 259429, 727369, 751761, 135071],
+    [1225614, 9004754, 9356209, 385614, 0, 3208903, 3095189, 6740120],
+];
+
+/// For each fixed point, the number of [`Chain`]s of length at least two
+/// with each least significant [`Residual`].
+const FIRST_COUNTS: [[u64; 8]; 2] = [
+    [557667, 93817, 93777, 303401, 160920, 215419, 218193, 117179],
+    [2020248, 1699146, 1718274, 853482, 3030965, 1727024, 1695022, 1276912],
+];
+
+/// The weight of the measured statistics relative to the statistics of the
+/// image being coded.
+const PRIOR_WEIGHT: u32 = 16;
+
+/// Constructs an [`AdaptiveSplit`] from measured counts.
+fn adaptive(f0: u64, f1: u64) -> AdaptiveSplit {
+    AdaptiveSplit::new(Split::new_ratio(f0 + 1, f1 + 1), PRIOR_WEIGHT)
+}
+
+/// Constructs a [`SymbolModel`] from measured counts.
+fn symbols(counts: &[u64]) -> SymbolModel {
+    SymbolModel::new(counts, PRIOR_WEIGHT)
+}
+
+// ----------------------------------------------------------------------------
+
+/// The number of levels of a [`Tree`] that have their own [`Context`]s.
+/// Deeper levels share the `Context`s of the deepest one.
+const NUM_LEVELS: usize = 8;
+
+/// The number of distinct values of [`Context::parent_length`].
+const NUM_BUCKETS: usize = 5;
+
+/// The number of distinct values of [`Context::neighbours`].
+const NUM_NEIGHBOURS: usize = 3;
+
+/// The total number of [`Context`]s.
+const NUM_CONTEXTS: usize = NUM_LEVELS * NUM_BUCKETS * NUM_NEIGHBOURS;
+
+/// Selects the probability models used to code one node of a [`Tree`].
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct Context {
+    /// The level of the node within the `Tree`.
+    pub level: usize,
+
+    /// The length of the [`Chain`] of the parent node, if any.
+    pub parent_length: Option<usize>,
+
+    /// The number of [`Tree::Branch`]es among the nodes immediately above and
+    /// to the left of the node, at the same level.
+    pub neighbours: usize,
+}
+
+impl Context {
+    /// Returns a different value for every distinguishable `Context`.
+    fn index(self) -> usize {
+        let level = std::cmp::min(self.level, NUM_LEVELS - 1);
+        let bucket = self.parent_length.map_or(0, |length| std::cmp::min(length, NUM_BUCKETS - 2) + 1);
+        (level * NUM_BUCKETS + bucket) * NUM_NEIGHBOURS + self.neighbours
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// The probability models used to code the [`Tree`]s of [`ShiftedBCC`]s of
+/// one channel of an image.
+///
+/// The models are initialised from statistics measured on a corpus of images,
+/// and adapt to the statistics of the image being coded. Therefore, the same
+/// sequence of calls must be made when decoding as when encoding, starting
+/// from a fresh `TreeModel`.
+///
+/// Whether a node is a [`Tree::Branch`], and the length of its [`Chain`], are
+/// coded using models selected by the node's [`Context`]. To compute the
+/// `Context`, the `TreeModel` remembers which nodes were coded as
+/// `Tree::Branch`es. `Tree`s must be coded in raster order.
+///
+/// Each `ShiftedBCC` is converted to a [`Chain`] and normalised by applying
+/// its recommended [`Symmetry`], so that its fixed point is one of two
+/// values. It is then coded as the `Symmetry`, the fixed point, the length of
+/// the `Chain`, and its [`Residual`]s from most to least significant.
+#[derive(Debug, Clone)]
+pub struct TreeModel {
+    /// For each [`Context`], the probability that a node is a
+    /// [`Tree::Branch`].
+    pub branch: Box<[AdaptiveSplit]>,
+
+    /// The probability of each [`Symmetry`].
+    pub symmetry: SymbolModel,
+
+    /// The probability that the fixed point is `ALL_RESIDUALS[4]` rather
+    /// than `ALL_RESIDUALS[0]`.
+    pub fixed_point: AdaptiveSplit,
+
+    /// For each [`Context`], the probability of each length of [`Chain`].
+    pub length: Box<[SymbolModel]>,
+
+    /// For each fixed point, the probability of each most significant
+    /// [`Residual`].
+    pub last: [SymbolModel; 2],
+
+    /// For each fixed point, the probability of each of the other
+    /// [`Residual`]s.
+    pub other: [SymbolModel; 2],
+
+    /// The number of levels of each [`Tree`].
+    order: usize,
+
+    /// For each level, which nodes have been coded as [`Tree::Branch`]es.
+    branched: Box<[Array<Grid, bool>]>,
+}
+
+impl TreeModel {
+    /// Constructs a `TreeModel` for an image of `size` [`Tree`]s, each with
+    /// `order` levels.
+    pub fn new(order: usize, size: Grid) -> Self {
+        let [leaf, branch] = LEAF_BRANCH_COUNTS;
+        let [fp0, fp4] = FIXED_POINT_COUNTS;
+        let branched = (0..order).map(|level| {
+            Array::from_fn((size.0 << level, size.1 << level), |_| false)
+        }).collect();
+        Self {
+            branch: vec![adaptive(leaf, branch); NUM_CONTEXTS].into(),
+            symmetry: symbols(&[0; 4]),
+            fixed_point: adaptive(fp0, fp4),
+            length: vec![symbols(&LENGTH_COUNTS); NUM_CONTEXTS].into(),
+            last: LAST_COUNTS.map(|counts| symbols(&counts)),
+            other: FIRST_COUNTS.map(|counts| symbols(&counts)),
+            order,
+            branched,
+        }
+    }
+
+    /// Returns the [`Context`] of the node at `pos`.
+    pub fn context(&self, pos: Position, parent_length: Option<usize>) -> Context {
+        let branched = &self.branched[pos.level];
+        let (y, x) = pos.yx;
+        let above = y > 0 && branched[(y - 1, x)];
+        let left = x > 0 && branched[(y, x - 1)];
+        Context {level: pos.level, parent_length, neighbours: above as usize + left as usize}
+    }
+
+    /// Returns the number of bits that `write_bcc()` would currently use to
+    /// write `bcc`.
+    pub fn bcc_cost(&self, context: Context, bcc: ShiftedBCC) -> f64 {
+        let chain = Chain::from_bcc(bcc);
+        let symmetry = chain.last_residual.recommend_symmetry();
+        let chain = chain.apply_symmetry(symmetry);
+        let length = chain.residuals.len();
+        assert!(length <= MAX_LENGTH, "Chain is too long");
+        let fp = (chain.last_residual.index() >> 2) & 1;
+        let mut cost = self.symmetry.cost(symmetry.index());
+        cost += self.fixed_point.split().cost(fp != 0);
+        cost += self.length[context.index()].cost(length);
+        let mut model = &self.last[fp];
+        for r in chain.residuals.iter().rev() {
+            cost += model.cost(r.index());
+            model = &self.other[fp];
+        }
+        cost
+    }
+
+    /// Write `bcc`, which is the payload of a node in `context`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`Chain`] of `bcc` is longer than [`MAX_LENGTH`].
+    /// [`to_digital()`](crate::quantize::to_digital) never makes such a
+    /// `ShiftedBCC`.
+    pub fn write_bcc(&mut self, w: &mut Writer<impl BitSink>, context: Context, bcc: ShiftedBCC) {
+        let chain = Chain::from_bcc(bcc);
+        let symmetry = chain.last_residual.recommend_symmetry();
+        let chain = chain.apply_symmetry(symmetry);
+        let length = chain.residuals.len();
+        assert!(length <= MAX_LENGTH, "Chain is too long");
+        w.write_symbol(&mut self.symmetry, symmetry.index());
+        let fp = (chain.last_residual.index() >> 2) & 1;
+        w.write_adaptive(&mut self.fixed_point, fp != 0);
+        w.write_symbol(&mut self.length[context.index()], length);
+        let mut model = &mut self.last[fp];
+        for r in chain.residuals.iter().rev() {
+            w.write_symbol(model, r.index());
+            model = &mut self.other[fp];
+        }
+    }
+
+    /// Read a value written by `write_bcc()`.
+    pub fn read_bcc(&mut self, r: &mut Reader<impl Iterator<Item=bool>>, context: Context) -> Option<ShiftedBCC> {
+        let symmetry: Symmetry = ALL_SYMMETRIES[r.read_symbol(&mut self.symmetry)?];
+        let fp = r.read_adaptive(&mut self.fixed_point)? as usize;
+        let length = r.read_symbol(&mut self.length[context.index()])?;
+        let mut residuals: Vec<Residual> = Vec::with_capacity(length);
+        let mut model = &mut self.last[fp];
+        for _ in 0..length {
+            residuals.push(ALL_RESIDUALS[r.read_symbol(model)?]);
+            model = &mut self.other[fp];
+        }
+        residuals.reverse();
+        let chain = Chain {residuals, last_residual: ALL_RESIDUALS[4 * fp]};
+        Some(chain.apply_symmetry(symmetry).to_bcc())
+    }
+
+    /// The recursive part of `write_tree()`.
+    fn write_node(&mut self, w: &mut Writer<impl BitSink>, pos: Position, parent_length: Option<usize>, tree: &Tree<ShiftedBCC>) {
+        if pos.level == self.order { return; }
+        let context = self.context(pos, parent_length);
+        match tree {
+            Tree::Branch(branch) => {
+                w.write_adaptive(&mut self.branch[context.index()], true);
+                self.branched[pos.level][pos.yx] = true;
+                self.write_bcc(w, context, branch.payload);
+                let length = Some(Chain::from_bcc(branch.payload).residuals.len());
+                pos.children().zip(branch.children.as_ref()).each(|(child_pos, child)| {
+                    self.write_node(w, child_pos, length, child);
+                });
+            },
+            Tree::Leaf => { w.write_adaptive(&mut self.branch[context.index()], false); },
+        }
+    }
+
+    /// Write the [`Tree`] at position `yx`.
+    pub fn write_tree(&mut self, w: &mut Writer<impl BitSink>, yx: Grid, tree: &Tree<ShiftedBCC>) {
+        self.write_node(w, Position {level: 0, yx}, None, tree);
+    }
+
+    /// The recursive part of `read_tree()`.
+    fn read_node(&mut self, r: &mut Reader<impl Iterator<Item=bool>>, pos: Position, parent_length: Option<usize>) -> Option<Tree<ShiftedBCC>> {
+        if pos.level == self.order { return Some(Tree::Leaf); }
+        let context = self.context(pos, parent_length);
+        if !r.read_adaptive(&mut self.branch[context.index()])? { return Some(Tree::Leaf); }
+        self.branched[pos.level][pos.yx] = true;
+        let payload = self.read_bcc(r, context)?;
+        let length = Some(Chain::from_bcc(payload).residuals.len());
+        let child_pos: Quad<Position> = pos.children().collect();
+        let [[a, b], [c, d]] = child_pos.0;
+        let children = Quad::new(
+            self.read_node(r, a, length)?, self.read_node(r, b, length)?,
+            self.read_node(r, c, length)?, self.read_node(r, d, length)?,
+        );
+        Some(Tree::branch(payload, children))
+    }
+
+    /// Read the [`Tree`] at position `yx`, written by `write_tree()`.
+    pub fn read_tree(&mut self, r: &mut Reader<impl Iterator<Item=bool>>, yx: Grid) -> Option<Tree<ShiftedBCC>> {
+        self.read_node(r, Position {level: 0, yx}, None)
+    }
+}
+
+/// Estimates costs using the current state of the models.
+///
+/// The [`Context`] of a node depends on its neighbours, which are not known,
+/// so one of them is assumed to be a [`Tree::Branch`].
+impl Rate for TreeModel {
+    fn leaf(&self, level: usize, parent: Option<ShiftedBCC>) -> f32 {
+        let context = rate_context(level, parent);
+        self.branch[context.index()].split().cost(false) as f32
+    }
+
+    fn branch(&self, level: usize, parent: Option<ShiftedBCC>, bcc: ShiftedBCC) -> f32 {
+        let context = rate_context(level, parent);
+        (self.branch[context.index()].split().cost(true) + self.bcc_cost(context, bcc)) as f32
+    }
+}
+
+/// The [`Context`] assumed by the [`Rate`] implementation of [`TreeModel`].
+fn rate_context(level: usize, parent: Option<ShiftedBCC>) -> Context {
+    let parent_length = parent.map(|bcc| Chain::from_bcc(bcc).residuals.len());
+    Context {level, parent_length, neighbours: 1}
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use multidimension::{Size, View, Array};
+
+    use super::*;
+    use crate::{Pyramid};
+    use crate::io::{load_image, Pixels, L};
+    use crate::quantize::{to_digital, LumaModel};
+    use crate::encode::{BitString};
+
+    #[test]
+    fn bccs() {
+        const RANGE: [f32; 7] = [-100.0, -6.0, -2.0, 0.0, 2.0, 4.0, 1000.0];
+        let mut bccs = Vec::new();
+        for &v in &RANGE {
+            for &h in &RANGE {
+                for &c in &RANGE {
+                    bccs.push(ShiftedBCC::new(v + 1.0, h, c + 0.5));
+                    bccs.push(ShiftedBCC::new(v, h - 1.0, c - 0.5));
+                }
+            }
+        }
+        let context = Context {level: 1, parent_length: Some(2), neighbours: 1};
+        let mut w = Writer::new(BitString::default());
+        let mut model = TreeModel::new(2, (1, 1));
+        for &bcc in &bccs { model.write_bcc(&mut w, context, bcc); }
+        let bs = w.close();
+        let mut r = Reader::new(bs.iter());
+        let mut model = TreeModel::new(2, (1, 1));
+        for &bcc in &bccs { assert_eq!(model.read_bcc(&mut r, context), Some(bcc)); }
+        // Compare the estimated cost with the actual size.
+        let mut model = TreeModel::new(2, (1, 1));
+        let mut cost = 0.0;
+        for &bcc in &bccs {
+            cost += model.bcc_cost(context, bcc);
+            model.write_bcc(&mut Writer::new(BitString::default()), context, bcc);
+        }
+        assert!((bs.len() as f64 - cost).abs() < 64.0);
+    }
+
+    #[test]
+    fn standard_images() {
+        let order = 5;
+        for entry in std::fs::read_dir("standard").unwrap() {
+            let path = entry.unwrap().path();
+            let pixels: Array<Grid, f32> = match load_image(path.to_str().unwrap()).unwrap() {
+                Pixels::L(pa) => pa.crop_to_multiple(1 << order).column(L).collect(),
+                _ => panic!("Not a luma image"),
+            };
+            let pyramid = Pyramid::from_pixels(order, true, pixels);
+            let mut trees = Vec::new();
+            pyramid.size().each(|yx| {
+                let tree = pyramid.get(Position {level: 0, yx});
+                let digital = to_digital(order, &LumaModel::default(), pyramid[yx], &tree);
+                trees.push((yx, digital));
+            });
+            let mut w = Writer::new(BitString::default());
+            let mut model = TreeModel::new(order, pyramid.size());
+            for (yx, tree) in &trees { model.write_tree(&mut w, *yx, tree); }
+            let bs = w.close();
+            let mut r = Reader::new(bs.iter());
+            let mut model = TreeModel::new(order, pyramid.size());
+            for (yx, tree) in &trees { assert_eq!(model.read_tree(&mut r, *yx).as_ref(), Some(tree)); }
+            assert!(r.close().next().is_none());
+        }
+    }
+}
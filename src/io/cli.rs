@@ -1,7 +1,16 @@
+use std::io::{Read, Write};
 use std::path::{Path};
-use clap::{Parser};
+use clap::{Args, Parser};
+use image::{ImageFormat};
 
 use crate::{Error, Result};
+use crate::colour::{ColourSpace};
+use crate::quantize::{LumaModel};
+use crate::codec::{self, Options, ChromaOptions, Header, Target, CHROMA_FACTOR, ALPHA_TOLERANCE};
+use super::{Depth, Pixels, ImageInfo, decode_image, writable_info, write_image_with_info};
+
+/// The path that means stdin or stdout.
+pub const STDIO: &str = "-";
 
 /// Strip the directory and file extension from a file path.
 fn file_stem(path: &str) -> Result<&str> {
@@ -12,26 +21,153 @@ fn file_stem(path: &str) -> Result<&str> {
 
 /// Constructs a default output path from `in_path` and `program_name`.
 ///
-/// - in_path - the input path.
+/// - in_path - the input path. If it is [`STDIO`], so is the output path.
 /// - program_name - the name of the program.
-pub fn default_out_path(in_path: &str, program_name: &str) -> Result<String> {
+/// - extension - the file extension of the output.
+pub fn default_out_path(in_path: &str, program_name: &str, extension: &str) -> Result<String> {
+    if in_path == STDIO { return Ok(STDIO.to_owned()); }
     let mut out_path = std::env::temp_dir();
-    out_path.push(format!("{}-{}.png", file_stem(in_path)?, program_name));
+    out_path.push(format!("{}-{}.{}", file_stem(in_path)?, program_name, extension));
     Ok(out_path.to_str().ok_or(Error("Invalid unicode"))?.to_owned())
 }
 
+/// Parses an image format from a file extension, such as "png".
+pub fn parse_format(s: &str) -> std::result::Result<ImageFormat, Error> {
+    ImageFormat::from_extension(s).ok_or(Error("Unknown image format"))
+}
+
+/// Read the whole of the file at `path`, or stdin if `path` is [`STDIO`].
+pub fn read_bytes(path: &str) -> Result<Vec<u8>> {
+    if path != STDIO { return Ok(std::fs::read(path)?); }
+    let mut bytes = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Write `bytes` to the file at `path`, or stdout if `path` is [`STDIO`].
+pub fn write_bytes(path: &str, bytes: &[u8]) -> Result {
+    if path != STDIO { return Ok(std::fs::write(path, bytes)?); }
+    std::io::stdout().lock().write_all(bytes)?;
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+
+/// Arguments that specify an input image.
+#[derive(Debug, Args)]
+pub struct Input {
+    /// Input path, or "-" for stdin. May be an image or an FVQ file.
+    pub in_path: String,
+
+    /// The colour space of the input image: "srgb", "display-p3",
+    /// "adobe-rgb" or "rec2020". Defaults to the colour space recorded in the
+    /// file, or sRGB if none is.
+    #[arg(short, long)]
+    pub colour_space: Option<ColourSpace>,
+
+    /// Rotate the input image according to its EXIF orientation, if any.
+    #[arg(long)]
+    pub orient: bool,
+}
+
+impl Input {
+    /// Loads the input image, honouring `colour_space` and `orient`.
+    ///
+    /// FVQ files are decoded. Their [`ImageInfo::depth`] is the
+    /// [`Header::depth`] of the original image.
+    pub fn load(&self) -> Result<(Pixels, ImageInfo)> {
+        let bytes = read_bytes(&self.in_path)?;
+        let (pixels, mut info) = if bytes.starts_with(&codec::MAGIC) {
+            let header = Header::read(&mut bytes.as_slice())?;
+            let (pixels, metadata) = codec::decode_with_metadata(&bytes)?;
+            let colour_space = self.colour_space.unwrap_or(header.colour_space);
+            (pixels, ImageInfo {depth: header.depth, colour_space, metadata})
+        } else {
+            decode_image(&bytes, ImageFormat::from_path(&self.in_path).ok(), self.colour_space)?
+        };
+        let pixels = if self.orient { info.apply_orientation(pixels) } else { pixels };
+        Ok((pixels, info))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Arguments that specify an output image.
+#[derive(Debug, Args)]
+pub struct Output {
+    /// Output path, or "-" for stdout. Defaults to a file in the temporary
+    /// directory, or stdout if the input is stdin.
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+
+    /// The format of the output image, as a file extension such as "png".
+    /// Defaults to the extension of the output path, or a format that can
+    /// store the output depth.
+    #[arg(short, long, value_parser = parse_format)]
+    pub format: Option<ImageFormat>,
+
+    /// The depth of the output image: "8", "16" or "32f". Defaults to the
+    /// depth of the input image.
+    #[arg(short, long)]
+    pub depth: Option<Depth>,
+}
+
+impl Output {
+    /// Returns `format`, or the format implied by `out_path`, or the
+    /// format of `depth`.
+    pub fn format(&self, depth: Depth) -> ImageFormat {
+        self.format.or_else(|| {
+            self.out_path.as_ref().and_then(|path| ImageFormat::from_path(path).ok())
+        }).unwrap_or(depth.format())
+    }
+
+    /// Returns `out_path` or `default_out_path(in_path, program_name, extension)`.
+    pub fn out_path(&self, in_path: &str, program_name: &str, extension: &str) -> Result<String> {
+        self.out_path.clone().map_or_else(|| default_out_path(in_path, program_name, extension), Ok)
+    }
+
+    /// Returns the `depth` or the specified default value.
+    pub fn depth(&self, default_depth: Depth) -> Depth {
+        self.depth.unwrap_or(default_depth)
+    }
+
+    /// Saves the output image to `out_path(in_path, program_name, ...)` in
+    /// `format(info.depth)`.
+    ///
+    /// Warns on stderr if the format cannot record `info.colour_space`, in
+    /// which case the image is converted to sRGB, or if any of
+    /// `info.metadata` is omitted. See [`writable_info()`].
+    pub fn save(&self, in_path: &str, program_name: &str, pixels: &Pixels, info: &ImageInfo) -> Result {
+        let format = self.format(info.depth);
+        let extension = format.extensions_str().first().ok_or(Error("Unknown image format"))?;
+        let out_path = self.out_path(in_path, program_name, extension)?;
+        let written = writable_info(info, format);
+        if written.colour_space != info.colour_space {
+            eprintln!("Warning: {:?} files cannot record the {:?} colour space, so the image is converted to sRGB", format, info.colour_space);
+        }
+        let (metadata, written) = (&info.metadata, &written.metadata);
+        for (name, data, written) in [("EXIF", &metadata.exif, &written.exif), ("XMP", &metadata.xmp, &written.xmp), ("ICC", &metadata.icc, &written.icc)] {
+            if data.is_some() && written.is_none() { eprintln!("Warning: {} data is omitted from the {:?} file", name, format); }
+        }
+        if out_path == STDIO {
+            write_image_with_info(pixels, info, &mut std::io::stdout().lock(), format)
+        } else {
+            write_image_with_info(pixels, info, &mut std::fs::File::create(out_path)?, format)
+        }
+    }
+}
+
 // ----------------------------------------------------------------------------
 
 #[derive(Debug, Parser)]
 #[command(about = "Process an image file.")]
 #[command(author, version, long_about = None)]
 pub struct InOutOrder {
-    /// Input path.
-    pub in_path: String,
+    #[command(flatten)]
+    pub input: Input,
 
-    /// Output path.
-    #[arg(short, long)]
-    pub out_path: Option<String>,
+    #[command(flatten)]
+    pub output: Output,
 
     /// The order of the wavelet pyramid.
     #[arg(short = 'n', long)]
@@ -39,9 +175,17 @@ pub struct InOutOrder {
 }
 
 impl InOutOrder {
-    /// Returns `out_path` or `default_out_path(program_name)`.
-    pub fn out_path(&self, program_name: &str) -> Result<String> {
-        self.out_path.clone().map_or_else(|| default_out_path(&self.in_path, program_name), Ok)
+    /// Loads the input image. See [`Input::load()`].
+    pub fn load(&self) -> Result<(Pixels, ImageInfo)> { self.input.load() }
+
+    /// Saves the output image. See [`Output::save()`].
+    pub fn save(&self, program_name: &str, pixels: &Pixels, info: &ImageInfo) -> Result {
+        self.output.save(&self.input.in_path, program_name, pixels, info)
+    }
+
+    /// Returns the `depth` or the specified default value.
+    pub fn depth(&self, default_depth: Depth) -> Depth {
+        self.output.depth(default_depth)
     }
 
     /// Returns the `order` or the specified default value.
@@ -49,3 +193,108 @@ impl InOutOrder {
         self.order.unwrap_or(default_order)
     }
 }
+
+// ----------------------------------------------------------------------------
+
+/// Arguments that control [`codec::encode()`].
+#[derive(Debug, Args)]
+pub struct Codec {
+    /// The perceptual model: "constant", "linear", "sqrt", "cbrt" or
+    /// "fourth-root".
+    #[arg(short, long, default_value = "cbrt")]
+    pub model: LumaModel,
+
+    /// The number of pixels per degree of visual angle. Larger values quantise
+    /// fine detail more coarsely. 0 disables this.
+    #[arg(short, long, default_value_t = 0.0)]
+    pub pixels_per_degree: f32,
+
+    /// The number of generations of the finest chroma wavelets to omit.
+    #[arg(long, default_value_t = 1)]
+    pub chroma_truncate: usize,
+
+    /// The factor by which chroma is less visible than luma.
+    #[arg(long, default_value_t = CHROMA_FACTOR)]
+    pub chroma_factor: f32,
+
+    /// The tolerance of the alpha channel, if any.
+    #[arg(long, default_value_t = ALPHA_TOLERANCE)]
+    pub alpha_tolerance: f32,
+
+    /// The number of units of distortion that are worth one bit.
+    #[arg(short, long, default_value_t = 0.0)]
+    pub lambda: f32,
+
+    /// Choose `lambda` to aim for this many bits per pixel.
+    #[arg(short, long, conflicts_with_all = ["lambda", "bytes"])]
+    pub bpp: Option<f64>,
+
+    /// Choose `lambda` to aim for this many bytes.
+    #[arg(long, conflicts_with = "lambda")]
+    pub bytes: Option<usize>,
+}
+
+impl Codec {
+    /// Returns the [`Options`] for encoding an image with the specified
+    /// `order` and [`ImageInfo`].
+    pub fn options(&self, order: usize, info: &ImageInfo) -> Options {
+        Options {
+            order,
+            model: self.model,
+            colour_space: info.colour_space,
+            depth: info.depth,
+            pixels_per_degree: self.pixels_per_degree,
+            chroma: [ChromaOptions {truncate: self.chroma_truncate, factor: self.chroma_factor}; 2],
+            alpha_tolerance: self.alpha_tolerance,
+            lambda: self.lambda,
+            metadata: info.metadata.clone(),
+        }
+    }
+
+    /// Returns the size to aim for, if any.
+    pub fn target(&self) -> Option<Target> {
+        match (self.bpp, self.bytes) {
+            (Some(bpp), _) => Some(Target::BitsPerPixel(bpp)),
+            (_, Some(bytes)) => Some(Target::Bytes(bytes)),
+            _ => None,
+        }
+    }
+
+    /// Compresses `pixels`, aiming for `target()` if specified, and reports
+    /// the size on stderr.
+    pub fn encode(&self, pixels: &Pixels, order: usize, info: &ImageInfo) -> Result<Vec<u8>> {
+        let options = self.options(order, info);
+        let bytes = if let Some(target) = self.target() {
+            let encoded = codec::encode_to_target(pixels, &options, target, 0.02)?;
+            eprintln!("lambda = {}, distortion = {:.3e}", encoded.lambda, encoded.distortion);
+            encoded.bytes
+        } else {
+            codec::encode(pixels, &options)?
+        };
+        let header = Header::read(&mut bytes.as_slice())?;
+        let bpp = (8 * bytes.len()) as f64 / (header.width * header.height) as f64;
+        eprintln!("{} bytes, {:.3} bits per pixel", bytes.len(), bpp);
+        Ok(bytes)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{load_image};
+
+    #[test]
+    fn load_fvq() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let options = Options {depth: Depth::U16, colour_space: ColourSpace::DisplayP3, ..Options::default()};
+        let path = default_out_path(&format!("lenna-{}.fvq", std::process::id()), "load-fvq", "fvq").unwrap();
+        std::fs::write(&path, codec::encode(&pixels, &options).unwrap()).unwrap();
+        let input = Input {in_path: path.clone(), colour_space: None, orient: false};
+        let (_, info) = input.load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(info.depth, Depth::U16);
+        assert_eq!(info.colour_space, ColourSpace::DisplayP3);
+    }
+}
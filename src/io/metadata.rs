@@ -0,0 +1,381 @@
+use image::{ImageFormat};
+
+/// The PNG file signature.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// The prefix of the JPEG `APP1` segment that contains EXIF data.
+const JPEG_EXIF: &[u8] = b"Exif\0\0";
+
+/// The prefix of the JPEG `APP1` segment that contains XMP data.
+const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// The prefix of the JPEG `APP2` segments that contain an ICC profile. Each
+/// is followed by a sequence number and the number of segments.
+const JPEG_ICC: &[u8] = b"ICC_PROFILE\0";
+
+/// The largest number of bytes of data in a JPEG segment.
+const JPEG_SEGMENT_MAX: usize = 0xffff - 2;
+
+/// The keyword of the PNG `iTXt` chunk that contains XMP data.
+const PNG_XMP: &[u8] = b"XML:com.adobe.xmp";
+
+/// The largest number of bytes of data in a PNG chunk.
+const PNG_CHUNK_MAX: usize = 0x7fffffff;
+
+/// The name of the ICC profile in the PNG `iCCP` chunk.
+const PNG_ICC: &[u8] = b"ICC profile";
+
+/// The EXIF tag that records the orientation of the image.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Metadata of an image file that is not needed to decode it, but which is
+/// worth preserving.
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq)]
+pub struct Metadata {
+    /// EXIF data, in TIFF format, starting with the byte order mark.
+    pub exif: Option<Vec<u8>>,
+
+    /// XMP data, as UTF-8 XML.
+    pub xmp: Option<Vec<u8>>,
+
+    /// An ICC profile.
+    pub icc: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Extracts the EXIF and XMP data from the bytes of a PNG, JPEG or WebP
+    /// file. Other formats yield `Metadata::default()`.
+    ///
+    /// The ICC profile is not extracted, because the image decoder finds it.
+    pub fn extract(file: &[u8]) -> Self {
+        let mut ret = Self::default();
+        if file.starts_with(PNG_SIGNATURE) {
+            for (chunk_type, data) in png_chunks(file) {
+                match chunk_type {
+                    b"eXIf" => { ret.exif = Some(data.to_vec()); },
+                    b"iTXt" => if let Some(xmp) = png_xmp(data) { ret.xmp = Some(xmp.to_vec()); },
+                    _ => {},
+                }
+            }
+        } else if file.starts_with(b"\xff\xd8") {
+            for (marker, data) in jpeg_segments(file) {
+                if marker != 0xe1 { continue; }
+                if let Some(exif) = data.strip_prefix(JPEG_EXIF) { ret.exif = Some(exif.to_vec()); }
+                if let Some(xmp) = data.strip_prefix(JPEG_XMP) { ret.xmp = Some(xmp.to_vec()); }
+            }
+        } else if file.starts_with(b"RIFF") && file.get(8..12) == Some(b"WEBP") {
+            for (chunk_type, data) in riff_chunks(&file[12..]) {
+                match chunk_type {
+                    b"EXIF" => { ret.exif = Some(data.strip_prefix(JPEG_EXIF).unwrap_or(data).to_vec()); },
+                    b"XMP " => { ret.xmp = Some(data.to_vec()); },
+                    _ => {},
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns the parts of `self` that can be stored in a file of the
+    /// specified format. PNG files can store everything. JPEG files can store
+    /// an ICC profile, and EXIF and XMP data of up to about 64KB each. Other
+    /// formats can store nothing.
+    pub fn storable(&self, format: ImageFormat) -> Self {
+        let fits = |data: &Option<Vec<u8>>, max: usize| data.clone().filter(|data| data.len() <= max);
+        let icc_segment_max = JPEG_SEGMENT_MAX - JPEG_ICC.len() - 2;
+        match format {
+            ImageFormat::Png => Self {
+                exif: fits(&self.exif, PNG_CHUNK_MAX),
+                xmp: fits(&self.xmp, PNG_CHUNK_MAX - PNG_XMP.len() - 5),
+                icc: fits(&self.icc, PNG_CHUNK_MAX / 2),
+            },
+            ImageFormat::Jpeg => Self {
+                exif: fits(&self.exif, JPEG_SEGMENT_MAX - JPEG_EXIF.len()),
+                xmp: fits(&self.xmp, JPEG_SEGMENT_MAX - JPEG_XMP.len()),
+                icc: fits(&self.icc, 255 * icc_segment_max),
+            },
+            _ => Self::default(),
+        }
+    }
+
+    /// Returns a copy of `file`, which must be in the specified format, with
+    /// `self` inserted. `self` must be [`storable()`](Self::storable()).
+    pub(super) fn embed(&self, file: &[u8], format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Png => {
+                let mut chunks = Vec::new();
+                if let Some(icc) = &self.icc {
+                    chunks.push((*b"iCCP", [PNG_ICC, b"\0\0", &zlib_stored(icc)].concat()));
+                }
+                if let Some(exif) = &self.exif { chunks.push((*b"eXIf", exif.clone())); }
+                if let Some(xmp) = &self.xmp {
+                    chunks.push((*b"iTXt", [PNG_XMP, b"\0\0\0\0\0", xmp].concat()));
+                }
+                insert_png_chunks(file, &chunks)
+            },
+            ImageFormat::Jpeg => {
+                let mut segments = Vec::new();
+                if let Some(exif) = &self.exif { segments.push((0xe1, [JPEG_EXIF, exif].concat())); }
+                if let Some(xmp) = &self.xmp { segments.push((0xe1, [JPEG_XMP, xmp].concat())); }
+                if let Some(icc) = &self.icc {
+                    let parts: Vec<&[u8]> = icc.chunks(JPEG_SEGMENT_MAX - JPEG_ICC.len() - 2).collect();
+                    for (i, part) in parts.iter().enumerate() {
+                        segments.push((0xe2, [JPEG_ICC, &[i as u8 + 1, parts.len() as u8], part].concat()));
+                    }
+                }
+                insert_jpeg_segments(file, &segments)
+            },
+            _ => file.to_vec(),
+        }
+    }
+
+    /// Returns `true` if `self` contains no metadata.
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none() && self.icc.is_none()
+    }
+
+    /// Returns the EXIF orientation of the image, from `1` to `8`, if it is
+    /// recorded.
+    pub fn orientation(&self) -> Option<u16> {
+        let exif = self.exif.as_deref()?;
+        let offset = orientation_offset(exif)?;
+        let orientation = read_u16(exif, offset, exif[0] == b'M')?;
+        (1..=8).contains(&orientation).then_some(orientation)
+    }
+
+    /// Changes the EXIF orientation of the image, if it is recorded.
+    pub fn set_orientation(&mut self, orientation: u16) {
+        let Some(exif) = self.exif.as_mut() else { return; };
+        let Some(offset) = orientation_offset(exif) else { return; };
+        let bytes = if exif[0] == b'M' { orientation.to_be_bytes() } else { orientation.to_le_bytes() };
+        exif[offset..offset + 2].copy_from_slice(&bytes);
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// Read a `u16` from `bytes` at `offset`.
+fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let b = bytes.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+}
+
+/// Read a `u32` from `bytes` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+}
+
+/// Returns the offset within `exif` of the value of the orientation tag, if
+/// it is present in the first IFD and has the expected type.
+fn orientation_offset(exif: &[u8]) -> Option<usize> {
+    let big_endian = match exif.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let ifd = read_u32(exif, 4, big_endian)? as usize;
+    let num_entries = read_u16(exif, ifd, big_endian)? as usize;
+    (0..num_entries).find_map(|i| {
+        let entry = ifd + 2 + 12 * i;
+        if read_u16(exif, entry, big_endian)? != ORIENTATION_TAG { return None; }
+        let is_short = read_u16(exif, entry + 2, big_endian)? == 3;
+        let value = entry + 8;
+        (is_short && value + 2 <= exif.len()).then_some(value)
+    })
+}
+
+/// Returns the type and data of each chunk of a PNG file.
+pub(super) fn png_chunks(png: &[u8]) -> impl Iterator<Item=(&[u8; 4], &[u8])> {
+    let mut offset = PNG_SIGNATURE.len();
+    std::iter::from_fn(move || {
+        let length = read_u32(png, offset, true)? as usize;
+        let chunk_type = png.get(offset + 4..offset + 8)?.try_into().ok()?;
+        let data = png.get(offset + 8..offset + 8 + length)?;
+        if chunk_type == b"IEND" { return None; }
+        offset += 12 + length;
+        Some((chunk_type, data))
+    })
+}
+
+/// Returns the CRC-32 of the bytes of a PNG chunk.
+fn png_crc(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 { crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg()); }
+    }
+    !crc
+}
+
+/// Returns a copy of `png` with `chunks` inserted after its `IHDR` chunk,
+/// which is the first chunk of every PNG file.
+pub(super) fn insert_png_chunks(png: &[u8], chunks: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let ihdr_end = PNG_SIGNATURE.len() + 12 + read_u32(png, PNG_SIGNATURE.len(), true).expect("Not a PNG file") as usize;
+    let mut ret = png[..ihdr_end].to_vec();
+    for (chunk_type, data) in chunks {
+        let length = u32::try_from(data.len()).expect("Chunk is too large");
+        ret.extend(length.to_be_bytes());
+        let start = ret.len();
+        ret.extend(chunk_type);
+        ret.extend(data);
+        let crc = png_crc(&ret[start..]);
+        ret.extend(crc.to_be_bytes());
+    }
+    ret.extend(&png[ihdr_end..]);
+    ret
+}
+
+/// Returns `data` in the zlib format, in uncompressed blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut ret = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(0xffff).collect() };
+    for (i, block) in blocks.iter().enumerate() {
+        ret.push((i + 1 == blocks.len()) as u8);
+        let length = block.len() as u16;
+        ret.extend(length.to_le_bytes());
+        ret.extend((!length).to_le_bytes());
+        ret.extend(*block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data {
+        a = (a + x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    ret.extend(((b << 16) | a).to_be_bytes());
+    ret
+}
+
+/// Returns the text of a PNG `iTXt` chunk, if it is uncompressed XMP data.
+fn png_xmp(data: &[u8]) -> Option<&[u8]> {
+    let data = data.strip_prefix(PNG_XMP)?.strip_prefix(b"\0\0\0")?;
+    // Skip the language tag and the translated keyword.
+    let mut fields = data.splitn(3, |&b| b == 0);
+    fields.next()?;
+    fields.next()?;
+    fields.next()
+}
+
+/// Returns the marker and data of each segment of a JPEG file that precedes
+/// the image data.
+fn jpeg_segments(jpeg: &[u8]) -> impl Iterator<Item=(u8, &[u8])> {
+    let mut offset = 2;
+    std::iter::from_fn(move || {
+        if *jpeg.get(offset)? != 0xff { return None; }
+        let marker = *jpeg.get(offset + 1)?;
+        if marker == 0xda || marker == 0xd9 { return None; }
+        let length = read_u16(jpeg, offset + 2, true)? as usize;
+        let data = jpeg.get(offset + 4..offset + 2 + length)?;
+        offset += 2 + length;
+        Some((marker, data))
+    })
+}
+
+/// Returns a copy of `jpeg` with `segments`, each a marker and its data,
+/// inserted after the start-of-image marker and the JFIF `APP0` segment, if
+/// any.
+fn insert_jpeg_segments(jpeg: &[u8], segments: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let offset = match jpeg_segments(jpeg).next() {
+        Some((0xe0, data)) => 6 + data.len(),
+        _ => 2,
+    };
+    let mut ret = jpeg[..offset].to_vec();
+    for (marker, data) in segments {
+        let length = u16::try_from(data.len() + 2).expect("Segment is too large");
+        ret.extend([0xff, *marker]);
+        ret.extend(length.to_be_bytes());
+        ret.extend(data);
+    }
+    ret.extend(&jpeg[offset..]);
+    ret
+}
+
+/// Returns the type and data of each chunk of a RIFF file, such as WebP,
+/// starting after the file header.
+fn riff_chunks(riff: &[u8]) -> impl Iterator<Item=(&[u8; 4], &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let chunk_type = riff.get(offset..offset + 4)?.try_into().ok()?;
+        let length = read_u32(riff, offset + 4, false)? as usize;
+        let data = riff.get(offset + 8..offset + 8 + length)?;
+        offset += 8 + length + (length & 1);
+        Some((chunk_type, data))
+    })
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Constructs little-endian EXIF data containing only an orientation.
+    fn exif(orientation: u16) -> Vec<u8> {
+        let mut exif = b"II*\0".to_vec();
+        exif.extend(8u32.to_le_bytes());
+        exif.extend(1u16.to_le_bytes());
+        exif.extend(ORIENTATION_TAG.to_le_bytes());
+        exif.extend(3u16.to_le_bytes());
+        exif.extend(1u32.to_le_bytes());
+        exif.extend(orientation.to_le_bytes());
+        exif.extend([0; 6]);
+        exif
+    }
+
+    #[test]
+    fn orientation() {
+        let mut metadata = Metadata {exif: Some(exif(6)), ..Metadata::default()};
+        assert_eq!(metadata.orientation(), Some(6));
+        metadata.set_orientation(1);
+        assert_eq!(metadata.orientation(), Some(1));
+        assert_eq!(Metadata::default().orientation(), None);
+    }
+
+    #[test]
+    fn jpeg() {
+        let exif = exif(3);
+        let mut jpeg = b"\xff\xd8".to_vec();
+        for data in [[JPEG_EXIF, &exif].concat(), [JPEG_XMP, b"<x/>"].concat()] {
+            jpeg.extend(b"\xff\xe1");
+            jpeg.extend((data.len() as u16 + 2).to_be_bytes());
+            jpeg.extend(data);
+        }
+        jpeg.extend(b"\xff\xda");
+        let metadata = Metadata::extract(&jpeg);
+        assert_eq!(metadata.exif, Some(exif));
+        assert_eq!(metadata.xmp.as_deref(), Some(&b"<x/>"[..]));
+        assert_eq!(metadata.orientation(), Some(3));
+    }
+
+    #[test]
+    fn png() {
+        let exif = exif(8);
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (chunk_type, data) in [
+            (b"eXIf", exif.clone()),
+            (b"iTXt", [PNG_XMP, b"\0\0\0en\0\0<x/>"].concat()),
+            (b"IEND", Vec::new()),
+        ] {
+            png.extend((data.len() as u32).to_be_bytes());
+            png.extend(chunk_type);
+            png.extend(data);
+            png.extend([0; 4]);
+        }
+        let metadata = Metadata::extract(&png);
+        assert_eq!(metadata.exif, Some(exif));
+        assert_eq!(metadata.xmp.as_deref(), Some(&b"<x/>"[..]));
+        assert!(Metadata::extract(b"P5 1 1 255 \0").is_empty());
+    }
+
+    #[test]
+    fn insert_chunks() {
+        assert_eq!(png_crc(b"IEND"), 0xae426082);
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(13u32.to_be_bytes());
+        png.extend(b"IHDR");
+        png.extend([0; 17]);
+        let png = insert_png_chunks(&png, &[(*b"tEXt", b"a\0b".to_vec()), (*b"zzzz", Vec::new())]);
+        let chunks: Vec<_> = png_chunks(&png).map(|(chunk_type, data)| (*chunk_type, data.to_vec())).collect();
+        assert_eq!(chunks, [(*b"IHDR", vec![0; 13]), (*b"tEXt", b"a\0b".to_vec()), (*b"zzzz", Vec::new())]);
+        assert_eq!(png[png.len() - 4..], png_crc(b"zzzz").to_be_bytes());
+    }
+}
@@ -1,15 +1,110 @@
-use image::{DynamicImage, ImageBuffer, Primitive};
+use std::io::{Read, Write};
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat, Primitive};
+use image::codecs::png::{PngDecoder};
+use image::codecs::jpeg::{JpegDecoder};
+use image::codecs::webp::{WebPDecoder};
 use multidimension::{View, Array};
 
 use super::{Grid};
+use super::colour::{ColourSpace};
 
 pub mod cli;
 
+mod profile;
+
+mod metadata;
+pub use metadata::{Metadata};
+
 mod pixels;
 pub use pixels::{PixelArray, Pixels, Channels, L, LA, RGB, RGBA};
 
 // ----------------------------------------------------------------------------
 
+/// The precision of each channel of an image file.
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Depth {
+    /// 8-bit integers, gamma-corrected.
+    #[default]
+    U8 = 0,
+
+    /// 16-bit integers, gamma-corrected.
+    U16 = 1,
+
+    /// 32-bit floating point, linear and not clamped, for high dynamic range
+    /// images. Only colour images can be saved at this depth; luma images are
+    /// converted to colour.
+    F32 = 2,
+}
+
+impl Depth {
+    /// Returns the `Depth` whose discriminant is `x`.
+    pub fn from_u8(x: u8) -> crate::Result<Self> {
+        Ok(match x {
+            0 => Depth::U8,
+            1 => Depth::U16,
+            2 => Depth::F32,
+            _ => Err(super::Error("Unknown depth"))?,
+        })
+    }
+
+    /// Returns a file extension of a format that can store this depth.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Depth::U8 | Depth::U16 => "png",
+            Depth::F32 => "exr",
+        }
+    }
+
+    /// Returns a format that can store this depth.
+    pub fn format(self) -> ImageFormat {
+        match self {
+            Depth::U8 | Depth::U16 => ImageFormat::Png,
+            Depth::F32 => ImageFormat::OpenExr,
+        }
+    }
+}
+
+impl std::str::FromStr for Depth {
+    type Err = super::Error;
+
+    /// Parses one of "8", "16" or "32f".
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "8" => Depth::U8,
+            "16" => Depth::U16,
+            "32f" | "32F" => Depth::F32,
+            _ => Err(super::Error("Unknown depth"))?,
+        })
+    }
+}
+
+/// Properties of an image file that are not part of its [`Pixels`].
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq)]
+pub struct ImageInfo {
+    /// The precision of each channel.
+    pub depth: Depth,
+
+    /// The colour space in which the file is encoded.
+    pub colour_space: ColourSpace,
+
+    /// The EXIF, XMP and ICC data of the file.
+    pub metadata: Metadata,
+}
+
+impl ImageInfo {
+    /// Rotates and/or reflects `pixels` according to the EXIF orientation in
+    /// `self.metadata`, if any, and resets the orientation to `1` so that it
+    /// is not applied twice.
+    pub fn apply_orientation(&mut self, pixels: Pixels) -> Pixels {
+        let Some(orientation) = self.metadata.orientation() else { return pixels; };
+        self.metadata.set_orientation(1);
+        pixels.orient(orientation)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
 fn to_f32<T: Primitive>(x: T) -> f32 {
     let mut x = x.to_f32().unwrap();
     x /= T::DEFAULT_MAX_VALUE.to_f32().unwrap();
@@ -17,66 +112,231 @@ fn to_f32<T: Primitive>(x: T) -> f32 {
 }
 
 /// The part of `load_image()` which is generic in the pixel format.
+///
+/// - transfer - the [`ColourSpace`] whose transfer function was applied to
+///   the colour channels, or `None` if they are linear.
 fn to_pixels<
     C: pixels::Channels,
     P: image::Pixel,
->(img: ImageBuffer<P, Vec<P::Subpixel>>) -> PixelArray<C> {
+>(img: ImageBuffer<P, Vec<P::Subpixel>>, transfer: Option<ColourSpace>) -> PixelArray<C> {
     assert_eq!(C::NUM_CHANNELS, P::CHANNEL_COUNT as usize);
     let size = (img.height() as usize, img.width() as usize);
     let pixels: Array<(Grid, C), P::Subpixel> = Array::new((size, ()), img.into_raw());
     let pixels = pixels.enumerate().map(|((_, c), x)| {
-        if c.is_alpha() { to_f32(x) } else { colcon::expand_gamma(to_f32(x)) }
+        if c.is_alpha() { return to_f32(x); }
+        match transfer {
+            Some(space) => space.expand_gamma(to_f32(x)),
+            None => num_traits::ToPrimitive::to_f32(&x).unwrap(),
+        }
     }).collect();
     PixelArray(pixels)
 }
 
+/// Decode an image and extract its ICC profile, if any.
+fn decode_with_icc<'a>(mut decoder: impl ImageDecoder<'a>) -> crate::Result<(DynamicImage, Option<Vec<u8>>)> {
+    let icc = decoder.icc_profile();
+    Ok((DynamicImage::from_decoder(decoder)?, icc))
+}
+
+/// Decode the bytes of an image file into a `Pixels`, and also return its
+/// [`ImageInfo`].
+///
+/// The format is detected from the contents, or is `format` if that fails.
+fn decode_image(
+    bytes: &[u8],
+    format: Option<ImageFormat>,
+    colour_space: Option<ColourSpace>,
+) -> crate::Result<(Pixels, ImageInfo)> {
+    let cursor = || std::io::Cursor::new(bytes);
+    let mut reader = image::io::Reader::new(cursor()).with_guessed_format()?;
+    if reader.format().is_none() { if let Some(format) = format { reader.set_format(format); } }
+    let (img, icc) = match reader.format() {
+        Some(ImageFormat::Png) => decode_with_icc(PngDecoder::new(cursor())?)?,
+        Some(ImageFormat::Jpeg) => decode_with_icc(JpegDecoder::new(cursor())?)?,
+        Some(ImageFormat::WebP) => decode_with_icc(WebPDecoder::new(cursor())?)?,
+        _ => (reader.decode()?, None),
+    };
+    let colour_space = colour_space.or_else(|| {
+        profile::png_cicp(bytes).and_then(|(primaries, transfer)| profile::from_cicp(primaries, transfer))
+    }).or_else(|| {
+        icc.as_deref().and_then(profile::from_icc)
+    }).unwrap_or_default();
+    let t = Some(colour_space);
+    let (pixels, depth) = match img {
+        DynamicImage::ImageLuma8(img) => (Pixels::L(to_pixels(img, t)), Depth::U8),
+        DynamicImage::ImageLumaA8(img) => (Pixels::LA(to_pixels(img, t)), Depth::U8),
+        DynamicImage::ImageRgb8(img) => (Pixels::RGB(to_pixels(img, t)), Depth::U8),
+        DynamicImage::ImageRgba8(img) => (Pixels::RGBA(to_pixels(img, t)), Depth::U8),
+        DynamicImage::ImageLuma16(img) => (Pixels::L(to_pixels(img, t)), Depth::U16),
+        DynamicImage::ImageLumaA16(img) => (Pixels::LA(to_pixels(img, t)), Depth::U16),
+        DynamicImage::ImageRgb16(img) => (Pixels::RGB(to_pixels(img, t)), Depth::U16),
+        DynamicImage::ImageRgba16(img) => (Pixels::RGBA(to_pixels(img, t)), Depth::U16),
+        DynamicImage::ImageRgb32F(img) => (Pixels::RGB(to_pixels(img, None)), Depth::F32),
+        DynamicImage::ImageRgba32F(img) => (Pixels::RGBA(to_pixels(img, None)), Depth::F32),
+        _ => Err(super::Error("Unknown image format"))?,
+    };
+    let pixels = match pixels {
+        Pixels::RGB(pa) => Pixels::RGB(colour_space.to_canonical(&pa)),
+        Pixels::RGBA(pa) => Pixels::RGBA(colour_space.to_canonical(&pa)),
+        pixels => pixels,
+    };
+    let metadata = Metadata {icc, ..Metadata::extract(bytes)};
+    Ok((pixels, ImageInfo {depth, colour_space, metadata}))
+}
+
+/// Read an image file from `r` into a `Pixels`, and also return its
+/// [`ImageInfo`]. The format is detected from the contents.
+///
+/// The colour space is detected from the file's PNG `cICP` chunk or ICC
+/// profile, if any, unless `colour_space` is specified. Otherwise, sRGB is
+/// assumed. Colour images are converted to linear Rec. 709.
+///
+/// The EXIF orientation, if any, is recorded in the metadata but not applied.
+/// See [`ImageInfo::apply_orientation()`].
+pub fn read_image_with_info(r: &mut impl Read, colour_space: Option<ColourSpace>) -> crate::Result<(Pixels, ImageInfo)> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    decode_image(&bytes, None, colour_space)
+}
+
+/// Load the specified file into a `Pixels`, and also return its
+/// [`ImageInfo`]. See [`read_image_with_info()`].
+///
+/// If the format cannot be detected from the contents, it is deduced from
+/// the file extension.
+pub fn load_image_with_info(name: &str, colour_space: Option<ColourSpace>) -> crate::Result<(Pixels, ImageInfo)> {
+    let bytes = std::fs::read(name)?;
+    decode_image(&bytes, ImageFormat::from_path(name).ok(), colour_space)
+}
+
 /// Load the specified file into a `Pixels`.
 pub fn load_image(name: &str) -> crate::Result<Pixels> {
-    let img = image::io::Reader::open(name)?.decode()?;
-    Ok(match img {
-        DynamicImage::ImageLuma8(img) => Pixels::L(to_pixels(img)),
-        DynamicImage::ImageLumaA8(img) => Pixels::LA(to_pixels(img)),
-        DynamicImage::ImageRgb8(img) => Pixels::RGB(to_pixels(img)),
-        DynamicImage::ImageRgba8(img) => Pixels::RGBA(to_pixels(img)),
-        DynamicImage::ImageLuma16(img) => Pixels::L(to_pixels(img)),
-        DynamicImage::ImageLumaA16(img) => Pixels::LA(to_pixels(img)),
-        DynamicImage::ImageRgb16(img) => Pixels::RGB(to_pixels(img)),
-        DynamicImage::ImageRgba16(img) => Pixels::RGBA(to_pixels(img)),
-        DynamicImage::ImageRgb32F(img) => Pixels::RGB(to_pixels(img)),
-        DynamicImage::ImageRgba32F(img) => Pixels::RGBA(to_pixels(img)),
-        _ => Err(super::Error("Unknown image format"))?,
-    })
+    Ok(load_image_with_info(name, None)?.0)
 }
 
 // ----------------------------------------------------------------------------
 
+/// The inverse of `to_f32()`. Rounds to the nearest integer, not towards zero,
+/// so that the rounding error of a transfer function cannot change the value
+/// of a pixel that is loaded and saved.
 fn from_f32<T: Primitive>(mut x: f32) -> T {
     x = x.clamp(0.0, 1.0);
     x *= T::DEFAULT_MAX_VALUE.to_f32().unwrap();
-    T::from(x).unwrap()
+    T::from(x.round()).unwrap()
 }
 
 /// The part of `save_image()` which is generic in the pixel format.
+///
+/// - transfer - the [`ColourSpace`] whose transfer function to apply to the
+///   colour channels, or `None` to leave them linear and unclamped.
 fn from_pixels<
     C: pixels::Channels,
     P: image::Pixel,
->(pixels: &PixelArray<C>) -> ImageBuffer<P, Vec<P::Subpixel>> {
+>(pixels: &PixelArray<C>, transfer: Option<ColourSpace>) -> ImageBuffer<P, Vec<P::Subpixel>> {
     assert_eq!(C::NUM_CHANNELS, P::CHANNEL_COUNT as usize);
     let ((height, width), ()) = pixels.0.size();
     let pixels: Array<(Grid, C), P::Subpixel> = (&pixels.0).enumerate().map(|((_, c), x)| {
-        if c.is_alpha() { from_f32(x) } else { from_f32(colcon::correct_gamma(x)) }
+        if c.is_alpha() { return from_f32(x); }
+        match transfer {
+            Some(space) => from_f32(space.correct_gamma(x)),
+            None => <P::Subpixel as num_traits::NumCast>::from(x).unwrap(),
+        }
     }).collect();
     ImageBuffer::from_raw(width as u32, height as u32, pixels.to_raw().into()).unwrap()
 }
 
-/// Save `pixels` to the specified file.
+/// Converts a luma image to a colour image.
+fn to_rgb<C: Channels, D: Channels>(pixels: &PixelArray<C>, map: &[C]) -> PixelArray<D> {
+    let channels: Vec<_> = map.iter().map(|&c| pixels.channel(c)).collect();
+    PixelArray::from_channels(&channels)
+}
+
+/// Convert `pixels`, which must be linear Rec. 709, to a `DynamicImage` with
+/// the specified [`ImageInfo`].
+fn encode_image(pixels: &Pixels, info: &ImageInfo) -> DynamicImage {
+    let space = info.colour_space;
+    let pixels = match pixels {
+        Pixels::L(pa) if info.depth == Depth::F32 => Pixels::RGB(to_rgb(pa, &[L, L, L])),
+        Pixels::LA(pa) if info.depth == Depth::F32 => Pixels::RGBA(to_rgb(pa, &[LA::Luma, LA::Luma, LA::Luma, LA::Alpha])),
+        Pixels::L(pa) => Pixels::L(pa.map_channels(|c| c)),
+        Pixels::LA(pa) => Pixels::LA(pa.map_channels(|c| c)),
+        Pixels::RGB(pa) => Pixels::RGB(space.from_canonical(pa)),
+        Pixels::RGBA(pa) => Pixels::RGBA(space.from_canonical(pa)),
+    };
+    let t = Some(space);
+    match (info.depth, &pixels) {
+        (Depth::U8, Pixels::L(pixels)) => DynamicImage::ImageLuma8(from_pixels(pixels, t)),
+        (Depth::U8, Pixels::LA(pixels)) => DynamicImage::ImageLumaA8(from_pixels(pixels, t)),
+        (Depth::U8, Pixels::RGB(pixels)) => DynamicImage::ImageRgb8(from_pixels(pixels, t)),
+        (Depth::U8, Pixels::RGBA(pixels)) => DynamicImage::ImageRgba8(from_pixels(pixels, t)),
+        (Depth::U16, Pixels::L(pixels)) => DynamicImage::ImageLuma16(from_pixels(pixels, t)),
+        (Depth::U16, Pixels::LA(pixels)) => DynamicImage::ImageLumaA16(from_pixels(pixels, t)),
+        (Depth::U16, Pixels::RGB(pixels)) => DynamicImage::ImageRgb16(from_pixels(pixels, t)),
+        (Depth::U16, Pixels::RGBA(pixels)) => DynamicImage::ImageRgba16(from_pixels(pixels, t)),
+        (Depth::F32, Pixels::RGB(pixels)) => DynamicImage::ImageRgb32F(from_pixels(pixels, None)),
+        (Depth::F32, Pixels::RGBA(pixels)) => DynamicImage::ImageRgba32F(from_pixels(pixels, None)),
+        (Depth::F32, _) => unreachable!(),
+    }
+}
+
+/// Returns the [`ImageInfo`] with which [`write_image_with_info()`] writes
+/// an image with the specified `info` in `format`.
+///
+/// The colour space is `info.colour_space` if the file can record it, and
+/// sRGB otherwise. PNG files can record it in a `cICP` chunk, except for
+/// Adobe RGB. PNG and JPEG files can record it in an ICC profile, if
+/// `info.metadata` has one that describes it.
+///
+/// The metadata is the part of `info.metadata` that the file can store. See
+/// [`Metadata::storable()`]. An ICC profile is omitted if it describes a
+/// different colour space, or if it is not recognised and the colour space
+/// is changed.
+pub fn writable_info(info: &ImageInfo, format: ImageFormat) -> ImageInfo {
+    let metadata = info.metadata.storable(format);
+    let icc_space = metadata.icc.as_deref().and_then(profile::from_icc);
+    let recordable = format == ImageFormat::Png && profile::to_cicp(info.colour_space).is_some() ||
+        icc_space == Some(info.colour_space);
+    let colour_space = if recordable { info.colour_space } else { ColourSpace::SRGB };
+    let icc = metadata.icc.filter(|_| icc_space.map_or(colour_space == info.colour_space, |space| space == colour_space));
+    ImageInfo {depth: info.depth, colour_space, metadata: Metadata {icc, ..metadata}}
+}
+
+/// Write `pixels`, which must be linear Rec. 709, to `w` in the specified
+/// format, with the specified [`ImageInfo`].
+///
+/// The pixels are converted to the colour space of
+/// [`writable_info(info, format)`](writable_info()), which the file records
+/// unless it is sRGB, and its metadata is written. The rest of
+/// `info.metadata` is omitted.
+pub fn write_image_with_info(pixels: &Pixels, info: &ImageInfo, w: &mut impl Write, format: ImageFormat) -> crate::Result<()> {
+    let info = writable_info(info, format);
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    encode_image(pixels, &info).write_to(&mut buffer, format)?;
+    let mut bytes = info.metadata.embed(buffer.get_ref(), format);
+    if format == ImageFormat::Png && info.colour_space != ColourSpace::SRGB {
+        if let Some((primaries, transfer)) = profile::to_cicp(info.colour_space) {
+            // The matrix coefficients are `0` (RGB), and the range is full.
+            bytes = metadata::insert_png_chunks(&bytes, &[(*b"cICP", vec![primaries, transfer, 0, 1])]);
+        }
+    }
+    w.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Save `pixels` to the specified file. The format is deduced from the file
+/// extension. See [`write_image_with_info()`].
+pub fn save_image_with_info(pixels: &Pixels, info: &ImageInfo, name: &str) -> crate::Result<()> {
+    let format = ImageFormat::from_path(name)?;
+    write_image_with_info(pixels, info, &mut std::fs::File::create(name)?, format)
+}
+
+/// Save `pixels` to the specified file, as sRGB with 8 bits per channel.
+///
+/// This does not preserve the depth of an image loaded by `load_image()`. To
+/// do that, as the command-line tools do, pass the [`ImageInfo`] returned by
+/// `load_image_with_info()` to `save_image_with_info()`.
 pub fn save_image(pixels: &Pixels, name: &str) -> crate::Result<()> {
-    Ok(match pixels {
-        Pixels::L(pixels) => DynamicImage::ImageLuma8(from_pixels(pixels)),
-        Pixels::LA(pixels) => DynamicImage::ImageLumaA8(from_pixels(pixels)),
-        Pixels::RGB(pixels) => DynamicImage::ImageRgb8(from_pixels(pixels)),
-        Pixels::RGBA(pixels) => DynamicImage::ImageRgba8(from_pixels(pixels)),
-    }.save(name)?)
+    save_image_with_info(pixels, &ImageInfo::default(), name)
 }
 
 // ----------------------------------------------------------------------------
@@ -98,4 +358,160 @@ mod tests {
             assert!(x.abs() < 0.01);
         });
     }
+
+    #[test]
+    fn round_trip() {
+        for space in [ColourSpace::SRGB, ColourSpace::AdobeRGB, ColourSpace::Rec2020] {
+            for x in 0..=u8::MAX {
+                let y = space.correct_gamma(space.expand_gamma(to_f32(x)));
+                assert_eq!(from_f32::<u8>(y), x, "{:?}", space);
+            }
+            for x in (0..=u16::MAX).step_by(257) {
+                let y = space.correct_gamma(space.expand_gamma(to_f32(x)));
+                assert_eq!(from_f32::<u16>(y), x, "{:?}", space);
+            }
+        }
+    }
+
+    #[test]
+    fn read_write() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let mut bytes = Vec::new();
+        write_image_with_info(&pixels, &ImageInfo::default(), &mut bytes, ImageFormat::Png).unwrap();
+        assert!(bytes.starts_with(b"\x89PNG"));
+        let (pixels2, info) = read_image_with_info(&mut bytes.as_slice(), None).unwrap();
+        assert_eq!(info.depth, Depth::U8);
+        pixels.luma().zip(pixels2.luma()).each(|(x, y)| assert_eq!(x, y));
+    }
+
+    #[test]
+    fn pad_crop() {
+        let pixels = PixelArray::<L>(Array::from_fn(((3, 2), ()), |((y, x), _)| (10 * y + x) as f32));
+        let padded = pixels.pad_to_multiple(4);
+        assert_eq!(padded.pixel_size(), (4, 4));
+        let row = |y| (0..4).map(|x| padded.at(((y, x), L))).collect::<Vec<_>>();
+        assert_eq!(row(0), [0.0, 1.0, 1.0, 0.0]);
+        assert_eq!(row(3), [20.0, 21.0, 21.0, 20.0]);
+        let cropped = padded.crop((3, 2));
+        (&cropped.0).zip(&pixels.0).each(|(x, y)| assert_eq!(x, y));
+        let tiny = pixels.crop((1, 1)).pad_to_multiple(4);
+        tiny.0.each(|x| assert_eq!(x, 0.0));
+    }
+
+    #[test]
+    fn orient() {
+        let pixels = PixelArray::<L>(Array::from_fn(((2, 3), ()), |((y, x), _)| (10 * y + x) as f32));
+        let row = |pa: &PixelArray<L>, y| (0..pa.pixel_size().1).map(|x| pa.at(((y, x), L))).collect::<Vec<_>>();
+        assert_eq!(row(&pixels.orient(1), 0), [0.0, 1.0, 2.0]);
+        assert_eq!(row(&pixels.orient(3), 0), [12.0, 11.0, 10.0]);
+        let rotated = pixels.orient(6);
+        assert_eq!(rotated.pixel_size(), (3, 2));
+        assert_eq!(row(&rotated, 0), [10.0, 0.0]);
+        assert_eq!(row(&pixels.orient(8), 0), [2.0, 12.0]);
+        assert_eq!(row(&pixels.orient(5), 2), [2.0, 12.0]);
+    }
+
+    #[test]
+    fn colour_space() {
+        let colours = [[0.2, 0.5, 0.8], [0.9, 0.1, 0.3], [0.4, 0.4, 0.4], [1.0, 0.0, 0.6]];
+        let pa = PixelArray::<RGB>(Array::from_fn(((2, 2), ()), |((y, x), c): (Grid, RGB)| colours[2 * y + x][c as usize]));
+        let pixels = Pixels::RGB(pa.map_channels(|c| c));
+        for (space, format, expected) in [
+            (ColourSpace::DisplayP3, ImageFormat::Png, ColourSpace::DisplayP3),
+            (ColourSpace::Rec2020, ImageFormat::Png, ColourSpace::Rec2020),
+            (ColourSpace::AdobeRGB, ImageFormat::Png, ColourSpace::SRGB),
+            (ColourSpace::DisplayP3, ImageFormat::Pnm, ColourSpace::SRGB),
+        ] {
+            let info = ImageInfo {colour_space: space, ..ImageInfo::default()};
+            assert_eq!(writable_info(&info, format).colour_space, expected);
+            let mut bytes = Vec::new();
+            write_image_with_info(&pixels, &info, &mut bytes, format).unwrap();
+            // The file contains the pixels in the `expected` colour space.
+            let raw = image::load_from_memory(&bytes).unwrap().into_rgb8().into_raw();
+            let values: Array<(Grid, RGB), u8> = (&expected.from_canonical(&pa).0).map(|x| from_f32(expected.correct_gamma(x))).collect();
+            assert_eq!(raw, values.to_raw().into_vec());
+            // The file records the colour space.
+            let (pixels2, info2) = read_image_with_info(&mut bytes.as_slice(), None).unwrap();
+            assert_eq!(info2.colour_space, expected);
+            let Pixels::RGB(pa2) = pixels2 else { panic!("Not a colour image"); };
+            (&pa.0).zip(&pa2.0).each(|(x, y)| assert!((x - y).abs() < 0.02, "{:?} {} {}", space, x, y));
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        let pixels = Pixels::L(PixelArray(Array::from_fn(((8, 8), ()), |((y, x), _)| (y + x) as f32 / 16.0)));
+        let metadata = Metadata {
+            exif: Some(b"II*\0\x08\0\0\0\0\0\0\0\0\0".to_vec()),
+            xmp: Some(b"<x/>".to_vec()),
+            icc: Some((0..100000).map(|i| (i % 251) as u8).collect()),
+        };
+        let info = ImageInfo {metadata: metadata.clone(), ..ImageInfo::default()};
+        for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+            assert_eq!(writable_info(&info, format), info);
+            let mut bytes = Vec::new();
+            write_image_with_info(&pixels, &info, &mut bytes, format).unwrap();
+            let (_, info2) = read_image_with_info(&mut bytes.as_slice(), None).unwrap();
+            assert_eq!(info2.metadata, metadata, "{:?}", format);
+        }
+        assert!(writable_info(&info, ImageFormat::OpenExr).metadata.is_empty());
+        // EXIF data that does not fit in a JPEG segment.
+        let exif = ImageInfo {metadata: Metadata {exif: Some(vec![0; 70000]), ..Metadata::default()}, ..ImageInfo::default()};
+        assert!(writable_info(&exif, ImageFormat::Jpeg).metadata.is_empty());
+        assert_eq!(writable_info(&exif, ImageFormat::Png), exif);
+        // An ICC profile records its colour space, but is omitted if it
+        // describes a different one.
+        let icc = profile::minimal_icc(profile::RED_COLORANTS[ColourSpace::AdobeRGB as usize]);
+        let metadata = Metadata {icc: Some(icc), ..Metadata::default()};
+        let adobe = ImageInfo {colour_space: ColourSpace::AdobeRGB, metadata, ..ImageInfo::default()};
+        for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+            assert_eq!(writable_info(&adobe, format), adobe);
+            let mut bytes = Vec::new();
+            write_image_with_info(&pixels, &adobe, &mut bytes, format).unwrap();
+            assert_eq!(read_image_with_info(&mut bytes.as_slice(), None).unwrap().1, adobe);
+        }
+        let written = writable_info(&ImageInfo {colour_space: ColourSpace::DisplayP3, ..adobe.clone()}, ImageFormat::Png);
+        assert_eq!(written.colour_space, ColourSpace::DisplayP3);
+        assert!(written.metadata.is_empty());
+        let written = writable_info(&ImageInfo {colour_space: ColourSpace::DisplayP3, ..adobe}, ImageFormat::Jpeg);
+        assert_eq!(written.colour_space, ColourSpace::SRGB);
+        assert!(written.metadata.is_empty());
+    }
+
+    /// Returns a path in the temporary directory that no other test process
+    /// uses.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("fvq-{}-{}", std::process::id(), name));
+        path.to_str().expect("Invalid unicode").to_owned()
+    }
+
+    #[test]
+    fn depth() {
+        let pixels = load_image("standard/lenna.png").unwrap();
+        let Pixels::L(pa) = &pixels else { panic!("Not a luma image"); };
+        for (depth, name, accuracy) in [
+            (Depth::U16, "lenna-16.png", 1e-4),
+            (Depth::F32, "lenna-32f.exr", 1e-6),
+        ] {
+            let name = temp_path(name);
+            save_image_with_info(&pixels, &ImageInfo {depth, ..ImageInfo::default()}, &name).unwrap();
+            let (pixels2, info) = load_image_with_info(&name, None).unwrap();
+            std::fs::remove_file(&name).unwrap();
+            assert_eq!(info.depth, depth);
+            let luma2 = match pixels2 {
+                Pixels::L(pa2) => pa2.channel(L),
+                Pixels::RGB(pa2) => pa2.channel(RGB::Green),
+                _ => panic!("Unexpected channels"),
+            };
+            pa.channel(L).zip(&luma2).each(|(x, y)| { assert!((x - y).abs() < accuracy); });
+        }
+        // HDR values are preserved.
+        let bright = Pixels::RGB(PixelArray(Array::from_fn(((2, 2), ()), |_| 4.0)));
+        let name = temp_path("bright.exr");
+        save_image_with_info(&bright, &ImageInfo {depth: Depth::F32, ..ImageInfo::default()}, &name).unwrap();
+        let Pixels::RGB(bright2) = load_image(&name).unwrap() else { panic!("Not a colour image"); };
+        std::fs::remove_file(&name).unwrap();
+        bright2.0.each(|x| assert_eq!(x, 4.0));
+        assert_eq!("32f".parse::<Depth>().unwrap(), Depth::F32);
+    }
 }
@@ -3,11 +3,12 @@ use std::mem::{transmute};
 use multidimension::{NonTuple, Index, StaticIndex, NewView, View, Array};
 
 use super::{Grid};
+use crate::colour;
 
 // ----------------------------------------------------------------------------
 
 /// An `Index` that distinguishes colour channels.
-pub trait Channels: StaticIndex {
+pub trait Channels: StaticIndex + NonTuple {
     /// The number of colour channels.
     const NUM_CHANNELS: usize = Self::ALL.len();
 
@@ -94,6 +95,13 @@ impl Channels for RGBA {
 
 // ----------------------------------------------------------------------------
 
+/// Maps `i` into the range `0..n` by repeatedly reflecting it about the ends
+/// of the range.
+fn reflect(i: usize, n: usize) -> usize {
+    let i = i % (2 * n);
+    if i < n { i } else { 2 * n - 1 - i }
+}
+
 /// A rectangular grid of pixels with colour channels indexed by `C`.
 pub struct PixelArray<C: Channels>(pub Array<(Grid, C), f32>);
 
@@ -108,6 +116,66 @@ impl<C: Channels> PixelArray<C> {
             |((y, x), c)| ((y + top, x + left), c)
         ).compose(self).collect()
     }
+
+    /// Extends `self` at the bottom and right to make the size a multiple of
+    /// `quantum`. The new pixels are a mirror image of the old ones, which
+    /// avoids creating edges that are expensive to code.
+    pub fn pad_to_multiple(&self, quantum: usize) -> Self {
+        let (height, width) = self.pixel_size();
+        assert!(height > 0 && width > 0, "Image is empty");
+        let new_size = (height.div_ceil(quantum) * quantum, width.div_ceil(quantum) * quantum);
+        <(Grid, C)>::all((new_size, ())).map(
+            |((y, x), c)| ((reflect(y, height), reflect(x, width)), c)
+        ).compose(self).collect()
+    }
+
+    /// Returns the top-left `size` pixels of `self`.
+    pub fn crop(&self, size: Grid) -> Self {
+        let (height, width) = self.pixel_size();
+        assert!(size.0 <= height && size.1 <= width, "Image is too small");
+        <(Grid, C)>::all((size, ())).compose(self).collect()
+    }
+
+    /// Rotates and/or reflects `self` according to an EXIF `orientation`,
+    /// from `1` to `8`, so that it displays the right way up.
+    pub fn orient(&self, orientation: u16) -> Self {
+        let (h, w) = self.pixel_size();
+        let new_size = if orientation >= 5 { (w, h) } else { (h, w) };
+        <(Grid, C)>::all((new_size, ())).map(|((y, x), c)| {
+            let yx = match orientation {
+                2 => (y, w - 1 - x),
+                3 => (h - 1 - y, w - 1 - x),
+                4 => (h - 1 - y, x),
+                5 => (x, y),
+                6 => (h - 1 - x, y),
+                7 => (h - 1 - x, w - 1 - y),
+                8 => (x, w - 1 - y),
+                _ => (y, x),
+            };
+            (yx, c)
+        }).compose(self).collect()
+    }
+
+    /// Returns the size of `self` in pixels.
+    pub fn pixel_size(&self) -> Grid { self.size().0 }
+
+    /// Returns channel `c` of `self`.
+    pub fn channel(&self, c: C) -> Array<Grid, f32> { self.column(c).collect() }
+
+    /// Constructs a `PixelArray` from one `Array` per channel, in the order
+    /// of `C::ALL`. The `Array`s must all be the same size.
+    pub fn from_channels(channels: &[Array<Grid, f32>]) -> Self {
+        assert_eq!(channels.len(), C::NUM_CHANNELS);
+        let size = channels[0].size();
+        for channel in channels { assert_eq!(channel.size(), size); }
+        Self(Array::from_fn((size, ()), |(yx, c): (Grid, C)| channels[StaticIndex::to_usize(c)].at(yx)))
+    }
+
+    /// Applies `f` to every channel of `self`.
+    pub fn map_channels(&self, mut f: impl FnMut(Array<Grid, f32>) -> Array<Grid, f32>) -> Self {
+        let channels: Vec<_> = C::ALL.iter().map(|&c| f(self.channel(c))).collect();
+        Self::from_channels(&channels)
+    }
 }
 
 impl<C: Channels> View for PixelArray<C> {
@@ -138,3 +206,94 @@ pub enum Pixels {
     RGB(PixelArray<RGB>),
     RGBA(PixelArray<RGBA>),
 }
+
+impl Pixels {
+    /// Returns the size of `self` in pixels.
+    pub fn size(&self) -> Grid {
+        match self {
+            Pixels::L(pa) => pa.pixel_size(),
+            Pixels::LA(pa) => pa.pixel_size(),
+            Pixels::RGB(pa) => pa.pixel_size(),
+            Pixels::RGBA(pa) => pa.pixel_size(),
+        }
+    }
+
+    /// Removes a border from `self` to make the size a multiple of `quantum`.
+    pub fn crop_to_multiple(&self, quantum: usize) -> Self {
+        match self {
+            Pixels::L(pa) => Pixels::L(pa.crop_to_multiple(quantum)),
+            Pixels::LA(pa) => Pixels::LA(pa.crop_to_multiple(quantum)),
+            Pixels::RGB(pa) => Pixels::RGB(pa.crop_to_multiple(quantum)),
+            Pixels::RGBA(pa) => Pixels::RGBA(pa.crop_to_multiple(quantum)),
+        }
+    }
+
+    /// Extends `self` at the bottom and right to make the size a multiple of
+    /// `quantum`. See [`PixelArray::pad_to_multiple()`].
+    pub fn pad_to_multiple(&self, quantum: usize) -> Self {
+        match self {
+            Pixels::L(pa) => Pixels::L(pa.pad_to_multiple(quantum)),
+            Pixels::LA(pa) => Pixels::LA(pa.pad_to_multiple(quantum)),
+            Pixels::RGB(pa) => Pixels::RGB(pa.pad_to_multiple(quantum)),
+            Pixels::RGBA(pa) => Pixels::RGBA(pa.pad_to_multiple(quantum)),
+        }
+    }
+
+    /// Returns the top-left `size` pixels of `self`.
+    pub fn crop(&self, size: Grid) -> Self {
+        match self {
+            Pixels::L(pa) => Pixels::L(pa.crop(size)),
+            Pixels::LA(pa) => Pixels::LA(pa.crop(size)),
+            Pixels::RGB(pa) => Pixels::RGB(pa.crop(size)),
+            Pixels::RGBA(pa) => Pixels::RGBA(pa.crop(size)),
+        }
+    }
+
+    /// Rotates and/or reflects `self` according to an EXIF `orientation`.
+    /// See [`PixelArray::orient()`].
+    pub fn orient(&self, orientation: u16) -> Self {
+        match self {
+            Pixels::L(pa) => Pixels::L(pa.orient(orientation)),
+            Pixels::LA(pa) => Pixels::LA(pa.orient(orientation)),
+            Pixels::RGB(pa) => Pixels::RGB(pa.orient(orientation)),
+            Pixels::RGBA(pa) => Pixels::RGBA(pa.orient(orientation)),
+        }
+    }
+
+    /// Returns every channel of `self`, including any alpha channel.
+    pub fn channels(&self) -> Vec<Array<Grid, f32>> {
+        match self {
+            Pixels::L(pa) => L::ALL.iter().map(|&c| pa.channel(c)).collect(),
+            Pixels::LA(pa) => LA::ALL.iter().map(|&c| pa.channel(c)).collect(),
+            Pixels::RGB(pa) => RGB::ALL.iter().map(|&c| pa.channel(c)).collect(),
+            Pixels::RGBA(pa) => RGBA::ALL.iter().map(|&c| pa.channel(c)).collect(),
+        }
+    }
+
+    /// Applies `f` to every channel of `self`, including any alpha channel.
+    pub fn map_channels(&self, f: impl FnMut(Array<Grid, f32>) -> Array<Grid, f32>) -> Self {
+        match self {
+            Pixels::L(pa) => Pixels::L(pa.map_channels(f)),
+            Pixels::LA(pa) => Pixels::LA(pa.map_channels(f)),
+            Pixels::RGB(pa) => Pixels::RGB(pa.map_channels(f)),
+            Pixels::RGBA(pa) => Pixels::RGBA(pa.map_channels(f)),
+        }
+    }
+
+    /// Returns the luma of `self`, ignoring any alpha channel.
+    ///
+    /// For colour images, this is [`colour::luma()`].
+    pub fn luma(&self) -> Array<Grid, f32> {
+        let y = colour::luma;
+        match self {
+            Pixels::L(pa) => pa.channel(L),
+            Pixels::LA(pa) => pa.channel(LA::Luma),
+            Pixels::RGB(pa) => Array::from_fn(pa.pixel_size(), |yx| {
+                y(pa.at((yx, RGB::Red)), pa.at((yx, RGB::Green)), pa.at((yx, RGB::Blue)))
+            }),
+            Pixels::RGBA(pa) => Array::from_fn(pa.pixel_size(), |yx| {
+                y(pa.at((yx, RGBA::Red)), pa.at((yx, RGBA::Green)), pa.at((yx, RGBA::Blue)))
+            }),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::colour::{ColourSpace};
+
+/// The red colorant (the `rXYZ` tag) of an ICC profile for each
+/// [`ColourSpace`], in the order of their discriminants. The values are
+/// relative to the D50 white point, as usual for ICC profiles.
+pub(super) const RED_COLORANTS: [[f32; 3]; 4] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.5151, 0.2412, -0.0011],
+    [0.6097, 0.3111, 0.0195],
+    [0.6734, 0.2790, -0.0019],
+];
+
+/// The largest difference between a measured and expected red colorant that
+/// is treated as a match.
+const COLORANT_TOLERANCE: f32 = 0.01;
+
+/// Read a big-endian `u32` from `bytes` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Returns the [`ColourSpace`] described by an ICC profile, if it is one that
+/// we recognise. The profile is recognised by its red colorant.
+pub fn from_icc(icc: &[u8]) -> Option<ColourSpace> {
+    if icc.get(16..20)? != b"RGB " { return None; }
+    let num_tags = read_u32(icc, 128)? as usize;
+    let offset = (0..num_tags).find_map(|i| {
+        let entry = 132 + 12 * i;
+        if icc.get(entry..entry + 4)? == b"rXYZ" { read_u32(icc, entry + 4) } else { None }
+    })? as usize;
+    if icc.get(offset..offset + 4)? != b"XYZ " { return None; }
+    let xyz: [f32; 3] = [0, 1, 2].map(|i| read_u32(icc, offset + 8 + 4 * i).map_or(f32::NAN, |x| x as i32 as f32 / 65536.0));
+    let index = RED_COLORANTS.iter().position(|colorant| {
+        (0..3).all(|i| (colorant[i] - xyz[i]).abs() < COLORANT_TOLERANCE)
+    })?;
+    ColourSpace::from_u8(index as u8).ok()
+}
+
+/// Returns the [`ColourSpace`] described by the colour primaries and transfer
+/// characteristics code points of ITU-T H.273, as found in a PNG `cICP`
+/// chunk, if it is one that we recognise.
+pub fn from_cicp(primaries: u8, transfer: u8) -> Option<ColourSpace> {
+    match (primaries, transfer) {
+        (1, 13) => Some(ColourSpace::SRGB),
+        (12, 13) => Some(ColourSpace::DisplayP3),
+        (9, 1 | 6 | 14 | 15) => Some(ColourSpace::Rec2020),
+        _ => None,
+    }
+}
+
+/// Returns the colour primaries and transfer characteristics code points of
+/// ITU-T H.273 that describe `space`, if there are any. The inverse of
+/// [`from_cicp()`].
+pub fn to_cicp(space: ColourSpace) -> Option<(u8, u8)> {
+    match space {
+        ColourSpace::SRGB => Some((1, 13)),
+        ColourSpace::DisplayP3 => Some((12, 13)),
+        ColourSpace::Rec2020 => Some((9, 1)),
+        ColourSpace::AdobeRGB => None,
+    }
+}
+
+/// Returns the colour primaries and transfer characteristics of a PNG file
+/// from its `cICP` chunk, if any.
+pub fn png_cicp(png: &[u8]) -> Option<(u8, u8)> {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") { return None; }
+    let (_, data) = super::metadata::png_chunks(png).find(|&(chunk_type, _)| chunk_type == b"cICP")?;
+    Some((*data.first()?, *data.get(1)?))
+}
+
+/// Constructs a minimal ICC profile with the specified red colorant, which
+/// [`from_icc()`] recognises if it is one of [`RED_COLORANTS`].
+#[cfg(test)]
+pub(super) fn minimal_icc(red: [f32; 3]) -> Vec<u8> {
+    let mut icc = vec![0; 128];
+    icc[16..20].copy_from_slice(b"RGB ");
+    icc.extend(1u32.to_be_bytes());
+    icc.extend(b"rXYZ");
+    icc.extend(144u32.to_be_bytes());
+    icc.extend(20u32.to_be_bytes());
+    icc.extend(b"XYZ \0\0\0\0");
+    for x in red { icc.extend(((x * 65536.0).round() as i32).to_be_bytes()); }
+    icc
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icc_profiles() {
+        for (i, &red) in RED_COLORANTS.iter().enumerate() {
+            assert_eq!(from_icc(&minimal_icc(red)), Some(ColourSpace::from_u8(i as u8).unwrap()));
+        }
+        assert_eq!(from_icc(&minimal_icc([0.3, 0.3, 0.3])), None);
+        assert_eq!(from_icc(&[0; 10]), None);
+    }
+
+    #[test]
+    fn cicp() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(4u32.to_be_bytes());
+        png.extend(b"cICP\x0c\x0d\x00\x01");
+        png.extend([0; 4]);
+        assert_eq!(png_cicp(&png), Some((12, 13)));
+        assert_eq!(from_cicp(12, 13), Some(ColourSpace::DisplayP3));
+        assert_eq!(png_cicp(&png[..18]), None);
+    }
+}
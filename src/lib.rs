@@ -29,6 +29,8 @@ pub type Small = (bool, bool);
 
 pub mod io;
 
+pub mod colour;
+
 mod quad;
 pub use quad::{Quad, Tree, Branch, Path, TreeTop};
 
@@ -38,3 +40,5 @@ pub use transform::{Position, Pyramid, VHC};
 pub mod quantize;
 
 pub mod encode;
+
+pub mod codec;
@@ -88,6 +88,7 @@ pub enum Tree<B> {
     Leaf,
 }
 
+#[allow(clippy::derivable_impls)]
 impl<B> Default for Tree<B> {
     fn default() -> Self { Self::Leaf }
 }
@@ -97,6 +98,18 @@ impl<B> Tree<B> {
     pub fn branch(payload: B, children: Quad<Self>) -> Self {
         Tree::Branch(Box::new(Branch {payload, children}))
     }
+
+    /// Returns a copy of `self` in which every node at level `depth` or
+    /// deeper is a [`Tree::Leaf`].
+    pub fn truncate(&self, depth: usize) -> Self where B: Clone {
+        match self {
+            Tree::Branch(branch) if depth > 0 => Tree::branch(
+                branch.payload.clone(),
+                branch.children.as_ref().map(|child| child.truncate(depth - 1)).collect(),
+            ),
+            _ => Tree::Leaf,
+        }
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -141,6 +154,7 @@ impl Path {
     }
 
     /// Append one [`Small`]. Panics if the `Path` is already full.
+    #[allow(clippy::identity_op)]
     pub fn push(&mut self, small: Small) {
         assert!(self.0 >= TOP_BIT, "Overflow");
         self.0 <<= 2;
@@ -149,6 +163,7 @@ impl Path {
     }
 
     /// The number of [`Small`]s that can be [`pop()`]ped.
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(self) -> usize {
         (31 - (self.0 ^ LIMIT).leading_zeros() as usize) / 2
     }
@@ -251,4 +266,13 @@ mod tests {
             assert_eq!(p, q);
         });
     }
+
+    #[test]
+    fn truncate() {
+        let leaves = || Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::Leaf);
+        let tree = Tree::branch(1, Quad::new(Tree::Leaf, Tree::branch(2, leaves()), Tree::Leaf, Tree::Leaf));
+        assert_eq!(tree.truncate(2), tree);
+        assert_eq!(tree.truncate(1), Tree::branch(1, leaves()));
+        assert_eq!(tree.truncate(0), Tree::Leaf);
+    }
 }
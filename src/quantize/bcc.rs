@@ -1,3 +1,7 @@
+use simple_vectors::{Vector};
+
+use super::{BCC, Coset};
+
 /// Represents a point of the shifted body-centred cubic lattice.
 ///
 /// We use such points to represent quantised wavelet coefficients. Wavelets
@@ -10,8 +14,9 @@
 /// any other lattice with the same density. We orient and scale the lattice
 /// such that the shortest lattice vectors are `(±1, ±1, ±1)` (of norm 3).
 ///
-/// `ShiftedBCC`s are formed from the points of the BCC lattice by adding a
-/// constant vector `b`. We choose `b` such that `(±1, 0, ½)` and `(0, ±1, -½)`
+/// `ShiftedBCC`s are formed from the points of the [`BCC`] lattice by adding
+/// a constant vector `b` ([`ShiftedBCC::SHIFT`]), i.e. they are a [`Coset`].
+/// We choose `b` such that `(±1, 0, ½)` and `(0, ±1, -½)`
 /// are `ShiftedBCC`s. These are in fact the nearest four `ShiftedBCC`s to the
 /// origin, which is not a `ShiftedBCC`. Interpreted as wavelet coefficients,
 /// they are related by 90° rotations.
@@ -36,13 +41,24 @@ pub struct ShiftedBCC {
     c: i16,
 }
 
-/// Round to the nearest even integer.
-fn round2(x: f32) -> f32 { 2.0 * (x * 0.5).round() }
+impl ShiftedBCC {
+    /// The vector `b` that is added to each point of [`BCC`].
+    pub const SHIFT: [f32; 3] = [1.0, 0.0, 0.5];
 
-/// L2 norm.
-fn norm(v: f32, h: f32, c: f32) -> f32 { v * v + h * h + c * c }
+    /// Returns the [`Coset`] of [`BCC`] whose points are `ShiftedBCC`s.
+    pub fn coset() -> Coset<BCC> { Coset::new(Vector::new(Self::SHIFT)) }
+
+    /// Returns the point of [`BCC`] that `self` represents in [`Self::coset()`].
+    pub fn to_bcc(self) -> BCC { BCC::new([self.v, self.h, self.c].map(i32::from)) }
+
+    /// The inverse of `to_bcc()`.
+    ///
+    /// Undefined if it is further from the origin than about `32767`.
+    pub fn from_bcc(bcc: BCC) -> Self {
+        let [v, h, c] = bcc.coordinates().map(|x| x as i16);
+        Self::new_inner(v, h, c)
+    }
 
-impl ShiftedBCC {
     fn new_inner(v: i16, h: i16, c: i16) -> Self {
         assert_eq!(v & 1, c & 1, "Not a quantisation point");
         assert_eq!(h & 1, c & 1, "Not a quantisation point");
@@ -71,19 +87,8 @@ impl ShiftedBCC {
     ///
     /// Undefined if it is further from the origin than about `32767`.
     pub fn quantize(v: f32, h: f32, c: f32) -> (Self, f32) {
-        let v1 = round2(v - 1.0) + 1.0;
-        let h1 = round2(h - 0.0) + 0.0;
-        let c1 = round2(c - 0.5) + 0.5;
-        let norm1 = norm(v - v1, h - h1, c - c1);
-        let v2 = round2(v + 0.0) - 0.0;
-        let h2 = round2(h + 1.0) - 1.0;
-        let c2 = round2(c + 0.5) - 0.5;
-        let norm2 = norm(v - v2, h - h2, c - c2);
-        if norm1 < norm2 {
-            (Self::new(v1, h1, c1), norm1)
-        } else {
-            (Self::new(v2, h2, c2), norm2)
-        }
+        let (bcc, error2) = Self::coset().quantize(Vector::new([v, h, c]));
+        (Self::from_bcc(bcc), error2)
     }
 
     /// Finds the nearest `ShiftedBCC` to `½ self`, and returns it and the
@@ -112,6 +117,11 @@ impl ShiftedBCC {
 #[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct Symmetry(u8);
 
+impl Symmetry {
+    /// Returns the position of `self` in [`ALL_SYMMETRIES`].
+    pub fn index(self) -> usize { self.0 as usize }
+}
+
 /// All possible [`Residual`]s.
 pub const ALL_SYMMETRIES: [Symmetry; 4] = [
     Symmetry(0), Symmetry(1), Symmetry(2), Symmetry(3),
@@ -145,6 +155,9 @@ const DELTAS: [(i16, i16, i16); 8] = [
 pub struct Residual(u8);
 
 impl Residual {
+    /// Returns the position of `self` in [`ALL_RESIDUALS`].
+    pub fn index(self) -> usize { self.0 as usize }
+
     /// Returns the components of `self`.
     pub fn vhc(self) -> (f32, f32, f32) { RESIDUALS[self.0 as usize] }
 
@@ -285,6 +298,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(boxed_slice_into_iter)]
     fn arrow() {
         for a in some_bccs().into_iter() {
             let (observed_b, observed_r) = a.arrow();
@@ -375,4 +389,22 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn coset() {
+        let coset = ShiftedBCC::coset();
+        for &a in some_bccs().iter() {
+            assert_eq!(ShiftedBCC::from_bcc(a.to_bcc()), a);
+            let (v, h, c) = a.vhc();
+            assert_eq!(coset.to_analogue(a.to_bcc()), Vector::new([v, h, c]));
+        }
+        // The nearest four `ShiftedBCC`s to the origin.
+        let nearby = coset.nearby(Vector::new([0.0; 3]), 1.25);
+        assert_eq!(nearby.len(), 4);
+        for (bcc, error2) in nearby {
+            let (v, h, c) = ShiftedBCC::from_bcc(bcc).vhc();
+            assert_eq!(error2, 1.25);
+            assert!((v * v + h * h, c.abs()) == (1.0, 0.5));
+        }
+    }
 }
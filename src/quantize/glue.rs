@@ -0,0 +1,343 @@
+//! The glued lattice designed in `glue.md`.
+
+use std::ops::{Add, Sub, Neg};
+use num_traits::{Zero};
+use simple_vectors::{Vector};
+
+use crate::{Grid};
+use super::{Lattice, Voronoi};
+
+/// The number of dimensions of [`H15`].
+pub const H15_DIMENSIONS: usize = 15;
+
+/// For each coordinate of an [`H15`], its position in the 4×4 grid of
+/// `glue.md`:
+///
+/// ```text
+///        Va Ha Ca
+///     Vd Vp Cb Hc
+///     Hd Cc Hp Vb
+///     Cd Hb Vc Cp
+/// ```
+///
+/// The coordinates are the `(V, H, C)` triplets of the parent `p`, then of
+/// its children `a`, `b`, `c` and `d` in the order of [`Quad`].
+///
+/// [`Quad`]: crate::Quad
+pub const LAYOUT: [Grid; H15_DIMENSIONS] = [
+    (1, 1), (2, 2), (3, 3),
+    (0, 1), (0, 2), (0, 3),
+    (2, 3), (3, 1), (1, 2),
+    (3, 2), (1, 3), (2, 1),
+    (1, 0), (2, 0), (3, 0),
+];
+
+/// Returns the index of coordinate `i` of an [`H15`] in the 15-bit Hamming
+/// code, from `1` to `15`. A set of indices is a code word iff their
+/// exclusive or is zero.
+fn hamming_index(i: usize) -> usize {
+    let (y, x) = LAYOUT[i];
+    4 * y + x
+}
+
+/// Returns the exclusive or of the Hamming indices of the odd coordinates.
+/// It is zero iff `data` is in [`H15`].
+fn syndrome(data: &[i32; H15_DIMENSIONS]) -> usize {
+    (0..H15_DIMENSIONS).filter(|&i| data[i] & 1 != 0).fold(0, |s, i| s ^ hamming_index(i))
+}
+
+// ----------------------------------------------------------------------------
+
+/// A point of the H15 lattice of `glue.md`, which quantises a family of five
+/// `(V, H, C)` triplets jointly. See [`LAYOUT`] for the order of the
+/// coordinates.
+///
+/// H15 is the product lattice BCC^5 glued by the 15-bit Hamming code.
+/// Equivalently, a point belongs to the lattice iff its coordinates are
+/// integers and the odd ones form a Hamming code word. Its shortest vectors
+/// are of norm 3 (code words of weight 3) and 4 (twice a unit vector).
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct H15(Vector<i32, H15_DIMENSIONS>);
+
+impl H15 {
+    /// Returns `true` if `data` is a point of `H15`.
+    pub fn contains(data: &[i32; H15_DIMENSIONS]) -> bool { syndrome(data) == 0 }
+
+    /// Constructs an `H15` given its coordinates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is not a point of the lattice.
+    pub fn new(data: [i32; H15_DIMENSIONS]) -> Self {
+        assert!(Self::contains(&data), "Not a lattice point");
+        H15(Vector::new(data))
+    }
+
+    /// Returns the coordinates of `self`.
+    pub fn coordinates(self) -> [i32; H15_DIMENSIONS] { self.0.into() }
+}
+
+impl Add for H15 {
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output { H15(self.0 + other.0) }
+}
+
+impl Sub for H15 {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output { H15(self.0 - other.0) }
+}
+
+impl Neg for H15 {
+    type Output = Self;
+    fn neg(self) -> Self::Output { H15(-self.0) }
+}
+
+impl Zero for H15 {
+    fn zero() -> Self { H15(Zero::zero()) }
+    fn is_zero(&self) -> bool { self.0.is_zero() }
+}
+
+impl Lattice for H15 {
+    type V = Vector<f32, H15_DIMENSIONS>;
+
+    fn to_analogue(self) -> Self::V {
+        let data: [i32; H15_DIMENSIONS] = self.0.into();
+        Vector::new(data.map(|d| d as f32))
+    }
+
+    /// Finds the nearest point exactly. See [`H15::quantize_partial()`].
+    fn quantize(analogue: Self::V) -> (Self, f32) {
+        let analogue: [f32; H15_DIMENSIONS] = analogue.into();
+        H15::quantize_partial(analogue.map(Coordinate::Analogue))
+    }
+}
+
+impl Voronoi for H15 {
+    /// The vectors `±2eᵢ`, and the minimal code words (those of weight at
+    /// most 5) with every pattern of signs.
+    fn relevant_vectors() -> Vec<Self> {
+        let mut ret = Vec::new();
+        for i in 0..H15_DIMENSIONS {
+            for sign in [2, -2] {
+                let mut data = [0; H15_DIMENSIONS];
+                data[i] = sign;
+                ret.push(H15::new(data));
+            }
+        }
+        for bits in 1..(1_u32 << H15_DIMENSIONS) {
+            let word: [i32; H15_DIMENSIONS] = std::array::from_fn(|i| ((bits >> i) & 1) as i32);
+            if bits.count_ones() > 5 || !H15::contains(&word) { continue; }
+            let support: Vec<usize> = (0..H15_DIMENSIONS).filter(|&i| word[i] != 0).collect();
+            for signs in 0..(1 << support.len()) {
+                let mut data = word;
+                for (j, &i) in support.iter().enumerate() {
+                    if (signs >> j) & 1 != 0 { data[i] = -1; }
+                }
+                ret.push(H15::new(data));
+            }
+        }
+        ret
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A coordinate of the point passed to [`H15::quantize_partial()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Coordinate {
+    /// The coordinate must be rounded to the nearest possible integer.
+    Analogue(f32),
+
+    /// The coordinate has already been chosen.
+    Fixed(i32),
+
+    /// The coordinate does not matter. It will be `0` or `1`.
+    Free,
+}
+
+impl Coordinate {
+    /// Returns the best even and the best odd value for `self`, and the
+    /// squared error of each.
+    fn candidates(self) -> [(i32, f32); 2] {
+        match self {
+            Coordinate::Analogue(a) => {
+                let even = 2.0 * (0.5 * a).round();
+                let odd = 2.0 * (0.5 * (a - 1.0)).round() + 1.0;
+                [(even as i32, (a - even).powi(2)), (odd as i32, (a - odd).powi(2))]
+            },
+            Coordinate::Fixed(d) => {
+                let mut ret = [(d, f32::INFINITY); 2];
+                ret[(d & 1) as usize].1 = 0.0;
+                ret
+            },
+            Coordinate::Free => [(0, 0.0), (1, 0.0)],
+        }
+    }
+}
+
+impl H15 {
+    /// Finds the nearest point to `coordinates`, and returns it and the
+    /// squared error of its [`Coordinate::Analogue`] coordinates.
+    ///
+    /// Each coordinate has a best even and a best odd value. Choosing between
+    /// them is a soft-decision decoding problem for the Hamming code, which is
+    /// solved exactly by dynamic programming over the 16 syndromes (a Viterbi
+    /// decoder on the trellis of the code).
+    ///
+    /// # Panics
+    ///
+    /// Panics if no point of `H15` has the [`Coordinate::Fixed`] coordinates.
+    pub fn quantize_partial(coordinates: [Coordinate; H15_DIMENSIONS]) -> (Self, f32) {
+        let candidates = coordinates.map(Coordinate::candidates);
+        // `best[s]` is the smallest error of the coordinates so far, given that
+        // the exclusive or of the Hamming indices of the odd ones is `s`.
+        let mut best = [f32::INFINITY; 16];
+        best[0] = 0.0;
+        let mut choices = [[false; 16]; H15_DIMENSIONS];
+        for (i, choice) in choices.iter_mut().enumerate() {
+            let h = hamming_index(i);
+            let [(_, error_even), (_, error_odd)] = candidates[i];
+            let mut new_best = [f32::INFINITY; 16];
+            for s in 0..16 {
+                let if_even = best[s] + error_even;
+                let if_odd = best[s ^ h] + error_odd;
+                choice[s] = if_odd < if_even;
+                new_best[s] = if_even.min(if_odd);
+            }
+            best = new_best;
+        }
+        let mut data = [0; H15_DIMENSIONS];
+        let mut s = 0;
+        for i in (0..H15_DIMENSIONS).rev() {
+            let is_odd = choices[i][s];
+            data[i] = candidates[i][is_odd as usize].0;
+            if is_odd { s ^= hamming_index(i); }
+        }
+        (H15::new(data), best[0])
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vector_space::{InnerSpace};
+    use crate::quantize::{Coset};
+    use crate::quantize::random::{Random};
+
+    /// Converts a 4×4 grid laid out as in `glue.md` into coordinates.
+    fn from_grid(grid: [[i32; 4]; 4]) -> [i32; H15_DIMENSIONS] {
+        LAYOUT.map(|(y, x)| grid[y][x])
+    }
+
+    /// The four parity checks of the Hamming code.
+    const PARITY_CHECKS: [[[i32; 4]; 4]; 4] = [
+        [[0, 0, 1, 1], [0, 0, 1, 1], [0, 0, 1, 1], [0, 0, 1, 1]],
+        [[0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 1, 1], [1, 1, 1, 1]],
+        [[0, 1, 0, 1], [0, 1, 0, 1], [0, 1, 0, 1], [0, 1, 0, 1]],
+        [[0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0], [1, 1, 1, 1]],
+    ];
+
+    /// The generators of the Hamming code that are in BCC^5.
+    const BCC_GENERATORS: [[[i32; 4]; 4]; 5] = [
+        [[0, 1, 1, 1], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+        [[0, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1], [0, 1, 0, 0]],
+        [[0, 0, 0, 0], [0, 0, 0, 1], [0, 1, 0, 0], [0, 0, 1, 0]],
+        [[0, 0, 0, 0], [1, 0, 0, 0], [1, 0, 0, 0], [1, 0, 0, 0]],
+        [[0, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]],
+    ];
+
+    /// The generators of the Hamming code that are in G^5.
+    const GLUE_GENERATORS: [[[i32; 4]; 4]; 6] = [
+        [[0, 1, 0, 0], [1, 1, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+        [[0, 0, 1, 0], [1, 0, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+        [[0, 0, 0, 1], [1, 0, 0, 1], [0, 0, 0, 0], [0, 0, 0, 0]],
+        [[0, 1, 0, 0], [0, 0, 0, 0], [1, 1, 0, 0], [0, 0, 0, 0]],
+        [[0, 0, 1, 0], [0, 0, 0, 0], [1, 0, 1, 0], [0, 0, 0, 0]],
+        [[0, 1, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 0, 0]],
+    ];
+
+    /// Returns `true` if every triplet of `data` is in BCC.
+    fn is_bcc5(data: &[i32; H15_DIMENSIONS]) -> bool {
+        data.chunks(3).all(|t| t[0] & 1 == t[1] & 1 && t[1] & 1 == t[2] & 1)
+    }
+
+    #[test]
+    fn generators() {
+        for check in PARITY_CHECKS {
+            let check = from_grid(check);
+            for g in BCC_GENERATORS.iter().chain(&GLUE_GENERATORS) {
+                let g = from_grid(*g);
+                assert!(H15::contains(&g));
+                let dot: i32 = check.iter().zip(&g).map(|(c, x)| c * x).sum();
+                assert_eq!(dot & 1, 0);
+            }
+        }
+        for g in BCC_GENERATORS { assert!(is_bcc5(&from_grid(g))); }
+        for g in GLUE_GENERATORS { assert!(!is_bcc5(&from_grid(g))); }
+        assert!(!H15::contains(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn minimal_norm() {
+        // Every non-zero lattice vector with coordinates in {-1, 0, 1} is a
+        // non-zero code word, and other non-zero vectors have norm at least 4.
+        let mut num_minimal = 0;
+        for bits in 1..(1 << H15_DIMENSIONS) {
+            let data: [i32; H15_DIMENSIONS] = std::array::from_fn(|i| (bits >> i) & 1);
+            if H15::contains(&data) {
+                let norm = H15::new(data).magnitude2();
+                assert!(norm >= 3);
+                if norm == 3 { num_minimal += 1; }
+            }
+        }
+        // The Hamming code has 35 words of weight 3.
+        assert_eq!(num_minimal, 35);
+        assert_eq!(H15::new(from_grid(GLUE_GENERATORS[0])).magnitude2(), 3);
+    }
+
+    #[test]
+    fn relevant_vectors() {
+        // 30 vectors `±2eᵢ`, and 35, 105 and 168 code words of weight 3, 4 and 5.
+        assert_eq!(H15::relevant_vectors().len(), 30 + 35 * 8 + 105 * 16 + 168 * 32);
+        let coset = Coset::<H15>::new(Zero::zero());
+        let nearby = coset.nearby(Zero::zero(), 3.0);
+        assert_eq!(nearby.len(), 1 + 35 * 8);
+    }
+
+    #[test]
+    fn partial() {
+        use Coordinate::*;
+        let mut coordinates = [Free; H15_DIMENSIONS];
+        coordinates[..3].copy_from_slice(&[Fixed(1), Fixed(0), Fixed(-2)]);
+        coordinates[3..6].copy_from_slice(&[Analogue(0.1), Analogue(0.9), Analogue(-0.2)]);
+        let (digital, error2) = H15::quantize_partial(coordinates);
+        let data = digital.coordinates();
+        assert_eq!(data[..3], [1, 0, -2]);
+        assert_eq!(data[3..6], [0, 1, 0]);
+        assert!((error2 - 0.06).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nearest_point() {
+        // Compare with brute force over all combinations of rounding.
+        let mut random = Random(1);
+        for _ in 0..20 {
+            let a: [f32; H15_DIMENSIONS] = std::array::from_fn(|_| random.uniform() * 6.0 - 3.0);
+            let (digital, error2) = H15::quantize(Vector::new(a));
+            let actual = (digital.to_analogue() - Vector::new(a)).magnitude2();
+            assert!((actual - error2).abs() < 1e-4);
+            for bits in 0..(1 << H15_DIMENSIONS) {
+                let data: [i32; H15_DIMENSIONS] = std::array::from_fn(|i| {
+                    let f = a[i].floor() as i32;
+                    f + ((bits >> i) & 1)
+                });
+                if !H15::contains(&data) { continue; }
+                let e2: f32 = (0..H15_DIMENSIONS).map(|i| (a[i] - data[i] as f32).powi(2)).sum();
+                assert!(e2 >= error2 - 1e-4);
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::hash::{Hash};
+use std::collections::{HashSet};
 use std::ops::{Add, Sub, Neg};
 use num_traits::{Zero, ToPrimitive};
 use vector_space::{InnerSpace};
@@ -17,6 +19,7 @@ fn vector_from_iter<T, const N: usize>(i: impl Iterator<Item = T>) -> Vector<T,
 }
 
 /// Map `f` over the elements of `v`.
+#[allow(clippy::redundant_closure)]
 fn map_vector<T, U, const N: usize>(
     v: Vector<T, N>,
     mut f: impl FnMut(T) -> U,
@@ -68,9 +71,20 @@ pub trait Lattice: Copy + Zero + PartialEq where
     }
 
     /// Returns the L2 norm of `self`, which must be an integer.
+    #[allow(clippy::clone_on_copy)]
     fn magnitude2(self) -> u64 { self.clone().scalar(self) }
 }
 
+/// A [`Lattice`] that can list its Voronoi-relevant vectors.
+pub trait Voronoi: Lattice + Eq + Hash {
+    /// Returns the Voronoi-relevant vectors of the lattice, i.e. those that
+    /// define the faces of the Voronoi cell of the origin. Every lattice point
+    /// can be reached from every other by adding relevant vectors, without
+    /// moving further from any given analogue point than the further of the
+    /// two. [`Coset::nearby()`] relies on this.
+    fn relevant_vectors() -> Vec<Self>;
+}
+
 impl Lattice for i32 {
     type V = f32;
 
@@ -82,6 +96,10 @@ impl Lattice for i32 {
     }
 }
 
+impl Voronoi for i32 {
+    fn relevant_vectors() -> Vec<Self> { vec![1, -1] }
+}
+
 impl<D: Lattice<V=f32>, const N: usize> Lattice for Vector<D, N> {
     type V = Vector<D::V, N>;
 
@@ -92,13 +110,27 @@ impl<D: Lattice<V=f32>, const N: usize> Lattice for Vector<D, N> {
     }
 }
 
+impl<D: Voronoi + Lattice<V=f32>, const N: usize> Voronoi for Vector<D, N> {
+    fn relevant_vectors() -> Vec<Self> {
+        let mut ret = Vec::new();
+        for i in 0..N {
+            for r in D::relevant_vectors() {
+                let mut v = Self::zero();
+                v[i] = r;
+                ret.push(v);
+            }
+        }
+        ret
+    }
+}
+
 // ----------------------------------------------------------------------------
 
 /// A point of the D_N lattice.
 ///
 /// A point belongs to the lattice iff its coordinates are integers with an
 /// even sum. This lattice is interesting because [`to_digital()`] is cheap.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct D<const N: usize>(Vector<i32, N>);
 
 impl<const N: usize> D<N> {
@@ -160,6 +192,348 @@ impl<const N: usize> Lattice for D<N> {
     }
 }
 
+impl<const N: usize> Voronoi for D<N> {
+    /// The vectors `±eᵢ ± eⱼ`, or `±2e₀` if `N` is `1`.
+    fn relevant_vectors() -> Vec<Self> {
+        let mut ret = Vec::new();
+        if N == 1 {
+            for sign in [2, -2] { ret.push(D(Vector::new([sign; N]))); }
+        }
+        for i in 0..N {
+            for j in (i + 1)..N {
+                for (si, sj) in [(1, 1), (1, -1), (-1, 1), (-1, -1)] {
+                    let mut v = Vector::<i32, N>::zero();
+                    v[i] = si;
+                    v[j] = sj;
+                    ret.push(D(v));
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// The face-centred cubic lattice FCC (a.k.a. `D3` or `A3`).
+pub type FCC = D<3>;
+
+/// The `D4` lattice, the densest packing in four dimensions.
+pub type D4 = D<4>;
+
+// ----------------------------------------------------------------------------
+
+/// A point of the body-centred cubic lattice, BCC (a.k.a. `D3*` or `A3*`).
+///
+/// A point belongs to the lattice iff its coordinates are integers that are
+/// all even or all odd. This is the convention of `glue.md`; [`ShiftedBCC`]
+/// is a coset of this lattice.
+///
+/// [`ShiftedBCC`]: super::ShiftedBCC
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct BCC(Vector<i32, 3>);
+
+impl BCC {
+    /// Constructs a `BCC` given its coordinates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is not a point of the lattice.
+    pub fn new(data: [i32; 3]) -> Self {
+        assert_eq!(data[0] & 1, data[2] & 1, "Not a lattice point");
+        assert_eq!(data[1] & 1, data[2] & 1, "Not a lattice point");
+        BCC(Vector::new(data))
+    }
+
+    /// Returns the coordinates of `self`.
+    pub fn coordinates(self) -> [i32; 3] { self.0.into() }
+}
+
+impl Add for BCC {
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output { BCC(self.0 + other.0) }
+}
+
+impl Sub for BCC {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output { BCC(self.0 - other.0) }
+}
+
+impl Neg for BCC {
+    type Output = Self;
+    fn neg(self) -> Self::Output { BCC(-self.0) }
+}
+
+impl Zero for BCC {
+    fn zero() -> Self { BCC(Zero::zero()) }
+    fn is_zero(&self) -> bool { self.0.is_zero() }
+}
+
+impl Lattice for BCC {
+    type V = Vector<f32, 3>;
+
+    fn to_analogue(self) -> Self::V { map_vector(self.0, i32::to_analogue) }
+
+    /// Rounds `analogue` to the nearest point with even coordinates and to the
+    /// nearest point with odd coordinates, and returns the nearer. Ties are
+    /// broken in favour of the odd point.
+    fn quantize(analogue: Self::V) -> (Self, f32) {
+        let even = map_vector(analogue, |a| 2.0 * (0.5 * a).round());
+        let odd = map_vector(analogue, |a| 2.0 * (0.5 * (a + 1.0)).round() - 1.0);
+        let error_even = (analogue - even).magnitude2();
+        let error_odd = (analogue - odd).magnitude2();
+        let (best, error2) = if error_even < error_odd { (even, error_even) } else { (odd, error_odd) };
+        (BCC(map_vector(best, |b| b.to_i32().expect("Overflow"))), error2)
+    }
+}
+
+impl Voronoi for BCC {
+    /// The vectors `(±1, ±1, ±1)` and `(±2, 0, 0)` and its permutations.
+    fn relevant_vectors() -> Vec<Self> {
+        let mut ret = Vec::new();
+        for bits in 0..8 {
+            ret.push(BCC::new([0, 1, 2].map(|i| if (bits >> i) & 1 != 0 { -1 } else { 1 })));
+        }
+        for i in 0..3 {
+            for sign in [2, -2] {
+                let mut data = [0; 3];
+                data[i] = sign;
+                ret.push(BCC::new(data));
+            }
+        }
+        ret
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A point of the `E8` lattice, the densest packing in eight dimensions.
+///
+/// `E8` is the union of [`D<8>`] and `D<8> + (½, ..., ½)`. We store the
+/// coordinates doubled, so a point belongs to the lattice iff its doubled
+/// coordinates are all even or all odd, and their sum is a multiple of `4`.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct E8(Vector<i32, 8>);
+
+impl E8 {
+    /// Constructs an `E8` given its coordinates multiplied by two.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `doubled` is not twice a point of the lattice.
+    pub fn new(doubled: [i32; 8]) -> Self {
+        assert!(doubled.iter().all(|&d| d & 1 == doubled[0] & 1), "Not a lattice point");
+        assert_eq!(doubled.iter().sum::<i32>() & 3, 0, "Not a lattice point");
+        E8(Vector::new(doubled))
+    }
+}
+
+impl Add for E8 {
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output { E8(self.0 + other.0) }
+}
+
+impl Sub for E8 {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output { E8(self.0 - other.0) }
+}
+
+impl Neg for E8 {
+    type Output = Self;
+    fn neg(self) -> Self::Output { E8(-self.0) }
+}
+
+impl Zero for E8 {
+    fn zero() -> Self { E8(Zero::zero()) }
+    fn is_zero(&self) -> bool { self.0.is_zero() }
+}
+
+impl Lattice for E8 {
+    type V = Vector<f32, 8>;
+
+    fn to_analogue(self) -> Self::V { map_vector(self.0, |d| 0.5 * d.to_analogue()) }
+
+    /// Rounds `analogue` to the nearest point of each of the two cosets of
+    /// [`D<8>`], and returns the nearer.
+    fn quantize(analogue: Self::V) -> (Self, f32) {
+        let half = Vector::new([0.5; 8]);
+        let (even, error_even) = D::<8>::quantize(analogue);
+        let (odd, error_odd) = D::<8>::quantize(analogue - half);
+        if error_even <= error_odd {
+            (E8(even.0 * 2), error_even)
+        } else {
+            (E8(map_vector(odd.0, |d| 2 * d + 1)), error_odd)
+        }
+    }
+}
+
+impl Voronoi for E8 {
+    /// The 240 shortest vectors: `±eᵢ ± eⱼ` and `(±½, ..., ±½)` with an even
+    /// number of minus signs.
+    fn relevant_vectors() -> Vec<Self> {
+        let mut ret: Vec<Self> = D::<8>::relevant_vectors().into_iter().map(|d| E8(d.0 * 2)).collect();
+        for bits in 0_u32..256 {
+            if bits.count_ones() & 1 != 0 { continue; }
+            ret.push(E8::new(std::array::from_fn(|i| if (bits >> i) & 1 != 0 { -1 } else { 1 })));
+        }
+        ret
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A point of the hexagonal lattice `A2`.
+///
+/// We embed `A2` in three dimensions, so that it is integral: a point belongs
+/// to the lattice iff its coordinates are integers whose sum is zero. The
+/// quantization error of a point outside the plane of the lattice includes
+/// its distance from the plane.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct A2(Vector<i32, 3>);
+
+impl A2 {
+    /// Constructs an `A2` given its coordinates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is not a point of the lattice.
+    pub fn new(data: [i32; 3]) -> Self {
+        assert_eq!(data.iter().sum::<i32>(), 0, "Not a lattice point");
+        A2(Vector::new(data))
+    }
+}
+
+impl Add for A2 {
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output { A2(self.0 + other.0) }
+}
+
+impl Sub for A2 {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output { A2(self.0 - other.0) }
+}
+
+impl Neg for A2 {
+    type Output = Self;
+    fn neg(self) -> Self::Output { A2(-self.0) }
+}
+
+impl Zero for A2 {
+    fn zero() -> Self { A2(Zero::zero()) }
+    fn is_zero(&self) -> bool { self.0.is_zero() }
+}
+
+impl Lattice for A2 {
+    type V = Vector<f32, 3>;
+
+    fn to_analogue(self) -> Self::V { map_vector(self.0, i32::to_analogue) }
+
+    /// Projects `analogue` onto the plane of the lattice, rounds each
+    /// coordinate, then corrects the sum by moving the coordinates that were
+    /// rounded furthest.
+    fn quantize(analogue: Self::V) -> (Self, f32) {
+        let a: [f32; 3] = analogue.into();
+        let mean = a.iter().sum::<f32>() / 3.0;
+        let p = a.map(|x| x - mean);
+        let mut d = p.map(|x| x.round() as i32);
+        let mut order = [0, 1, 2];
+        // Sort by how far each coordinate was rounded up.
+        order.sort_by(|&i, &j| (d[i] as f32 - p[i]).total_cmp(&(d[j] as f32 - p[j])));
+        let sum: i32 = d.iter().sum();
+        if sum > 0 {
+            for &i in order.iter().rev().take(sum as usize) { d[i] -= 1; }
+        } else {
+            for &i in order.iter().take(-sum as usize) { d[i] += 1; }
+        }
+        let error2 = (0..3).map(|i| (a[i] - d[i] as f32).powi(2)).sum();
+        (A2::new(d), error2)
+    }
+}
+
+impl Voronoi for A2 {
+    /// The six shortest vectors, which are the permutations of `±(1, -1, 0)`.
+    fn relevant_vectors() -> Vec<Self> {
+        let mut ret = Vec::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                if i == j { continue; }
+                let mut data = [0; 3];
+                data[i] = 1;
+                data[j] = -1;
+                ret.push(A2::new(data));
+            }
+        }
+        ret
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A coset of a [`Lattice`] `L`, i.e. the points `shift + digital` where
+/// `digital` is an `L`.
+///
+/// A coset is a better quantiser than its lattice if the origin should not be
+/// a quantisation point. For example, [`ShiftedBCC`] is a coset of [`BCC`].
+///
+/// [`ShiftedBCC`]: super::ShiftedBCC
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Coset<L: Lattice> {
+    /// The point of the coset that represents the origin of `L`.
+    shift: L::V,
+}
+
+impl<L: Lattice> Coset<L> {
+    /// Constructs a `Coset` given its shift vector.
+    pub fn new(shift: L::V) -> Self { Self {shift} }
+
+    /// Returns the point of `self` that represents the origin of `L`.
+    pub fn shift(&self) -> L::V { self.shift }
+
+    /// Converts `digital` to the analogue point of `self` that it represents.
+    pub fn to_analogue(&self, digital: L) -> L::V { self.shift + digital.to_analogue() }
+
+    /// Rounds `analogue` to the nearest point of `self` and adds the square
+    /// of the quantization error to `error2`.
+    pub fn to_digital(&self, analogue: L::V, error2: &mut f32) -> L {
+        L::to_digital(analogue - self.shift, error2)
+    }
+
+    /// Rounds `analogue` to the nearest point of `self` and returns the square
+    /// of the quantization error.
+    pub fn quantize(&self, analogue: L::V) -> (L, f32) { L::quantize(analogue - self.shift) }
+
+    /// Returns the square of the distance from `analogue` to the point of
+    /// `self` represented by `digital`.
+    pub fn error2(&self, analogue: L::V, digital: L) -> f32 {
+        (analogue - self.to_analogue(digital)).magnitude2()
+    }
+
+    /// Returns every point of `self` whose squared distance from `analogue` is
+    /// at most `radius2`, with its squared distance, nearest first.
+    ///
+    /// This searches outwards from the nearest point along the
+    /// [`Voronoi::relevant_vectors()`].
+    pub fn nearby(&self, analogue: L::V, radius2: f32) -> Vec<(L, f32)> where L: Voronoi {
+        let (nearest, error2) = self.quantize(analogue);
+        if error2 > radius2 { return Vec::new(); }
+        let relevant_vectors = L::relevant_vectors();
+        let mut ret = vec![(nearest, error2)];
+        let mut seen = HashSet::from([nearest]);
+        let mut done = 0;
+        while done < ret.len() {
+            let (point, _) = ret[done];
+            done += 1;
+            for &r in &relevant_vectors {
+                let neighbour = point + r;
+                let error2 = self.error2(analogue, neighbour);
+                if error2 <= radius2 && seen.insert(neighbour) {
+                    ret.push((neighbour, error2));
+                }
+            }
+        }
+        ret.sort_by(|(_, e1), (_, e2)| e1.total_cmp(e2));
+        ret
+    }
+}
+
 // ----------------------------------------------------------------------------
 
 #[cfg(test)]
@@ -167,6 +541,7 @@ mod tests {
     use super::*;
 
     use std::fmt::{Debug};
+    use crate::quantize::random::{Random};
 
     fn check<L: Debug + Lattice>(analogue: L::V, digital: L, error2: f32) {
         let (d, e) = L::quantize(analogue);
@@ -182,4 +557,100 @@ mod tests {
         check(Vector::new([0.0, 1.0, 0.25]), D::new([0, 1, 1]), 0.5625);
         check(Vector::new([2.0, 1.0, 1.75]), D::new([2, 1, 1]), 0.5625);
     }
+
+    #[test]
+    fn bcc() {
+        check(Vector::new([0.25, 0.0, -0.5]), BCC::new([0, 0, 0]), 0.3125);
+        check(Vector::new([0.75, 0.5, 0.75]), BCC::new([1, 1, 1]), 0.375);
+        check(Vector::new([1.75, 0.0, 0.0]), BCC::new([2, 0, 0]), 0.0625);
+        check(Vector::new([-1.0, 3.0, 0.5]), BCC::new([-1, 3, 1]), 0.25);
+    }
+
+    #[test]
+    fn nearby() {
+        // Compare with brute force.
+        let coset = Coset::<D<3>>::new(Vector::new([0.5, 0.0, 0.25]));
+        let analogue = Vector::new([0.3, -0.8, 1.1]);
+        let nearby = coset.nearby(analogue, 3.0);
+        let mut count = 0;
+        for x in -3..4 {
+            for y in -3..4 {
+                for z in -3..4 {
+                    if (x + y + z) & 1 != 0 { continue; }
+                    let d = D::new([x, y, z]);
+                    let error2 = coset.error2(analogue, d);
+                    if error2 <= 3.0 {
+                        count += 1;
+                        assert!(nearby.iter().any(|&(p, e)| p == d && e == error2));
+                    }
+                }
+            }
+        }
+        assert_eq!(nearby.len(), count);
+        assert!(nearby.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(nearby[0].0, coset.quantize(analogue).0);
+    }
+
+    #[test]
+    fn e8() {
+        check(Vector::new([0.0; 8]), E8::zero(), 0.0);
+        check(Vector::new([0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 0.25]), E8::new([1; 8]), 0.0625);
+        check(Vector::new([0.75, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]), E8::zero(), 0.5625);
+        assert_eq!(E8::relevant_vectors().len(), 240);
+        assert!(E8::relevant_vectors().into_iter().all(|v| v.magnitude2() == 2));
+    }
+
+    #[test]
+    fn a2() {
+        check(Vector::new([0.9, -0.8, 0.0]), A2::new([1, -1, 0]), 0.05);
+        check(Vector::new([0.25, 0.25, -0.5]), A2::zero(), 0.375);
+        check(Vector::new([0.75, 0.5, -1.25]), A2::new([1, 0, -1]), 0.375);
+        check(Vector::new([1.0, 1.0, 1.0]), A2::zero(), 3.0);
+    }
+
+    /// Estimates the normalised second moment of `L`, i.e. the mean squared
+    /// quantisation error per dimension of a lattice with unit volume.
+    ///
+    /// - n - the number of dimensions.
+    /// - volume - the volume of the Voronoi cell.
+    /// - basis - vectors that span a fundamental region of the lattice.
+    fn nsm<L: Lattice>(n: usize, volume: f64, basis: &[L::V]) -> f64 {
+        const SAMPLES: usize = 20000;
+        let mut random = Random(1);
+        let mut total = 0.0;
+        for _ in 0..SAMPLES {
+            let mut analogue = L::V::zero();
+            for &b in basis { analogue = analogue + b * random.uniform(); }
+            total += L::quantize(analogue).1 as f64;
+        }
+        total / (SAMPLES * n) as f64 / volume.powf(2.0 / n as f64)
+    }
+
+    /// Returns the vectors `2eᵢ` in `N` dimensions.
+    fn cube<const N: usize>() -> Vec<Vector<f32, N>> {
+        (0..N).map(|i| {
+            let mut v = Vector::<f32, N>::zero();
+            v[i] = 2.0;
+            v
+        }).collect()
+    }
+
+    /// Asserts that `observed` is within 1% of `expected`.
+    fn assert_close(observed: f64, expected: f64) {
+        assert!((observed / expected - 1.0).abs() < 0.01, "{} is not close to {}", observed, expected);
+    }
+
+    #[test]
+    fn normalised_second_moment() {
+        // Published values from Conway and Sloane, "Sphere Packings, Lattices
+        // and Groups", Table 2.3.
+        assert_close(nsm::<i32>(1, 1.0, &[1.0]), 1.0 / 12.0);
+        assert_close(nsm::<Vector<i32, 2>>(2, 1.0, &cube::<2>()), 1.0 / 12.0);
+        let a2_basis = [Vector::new([1.0, -1.0, 0.0]), Vector::new([0.0, 1.0, -1.0])];
+        assert_close(nsm::<A2>(2, 3.0_f64.sqrt(), &a2_basis), 0.080188);
+        assert_close(nsm::<BCC>(3, 4.0, &cube::<3>()), 0.078543);
+        assert_close(nsm::<FCC>(3, 2.0, &cube::<3>()), 0.078745);
+        assert_close(nsm::<D4>(4, 2.0, &cube::<4>()), 0.076603);
+        assert_close(nsm::<E8>(8, 1.0, &cube::<8>()), 0.071682);
+    }
 }
@@ -0,0 +1,110 @@
+use multidimension::{Size, View, Array};
+
+use crate::{Grid, VHC};
+use super::{PerceptualModel};
+
+/// The quantisation step of the low-frequency image, in units of the
+/// tolerance at the predicted brightness.
+const LOW_STEP: f32 = 1.0;
+
+/// Predicts the sample at `(y, x)` of the low-frequency image from its
+/// neighbours above and to the left, which must already be known.
+///
+/// This is the median edge detector (MED) of LOCO-I: it chooses the left or
+/// above neighbour if there appears to be an edge, and otherwise assumes that
+/// the image is locally planar.
+fn predict(low: &Array<Grid, f32>, (y, x): Grid) -> f32 {
+    match (y, x) {
+        (0, 0) => 0.0,
+        (0, _) => low[(0, x - 1)],
+        (_, 0) => low[(y - 1, 0)],
+        _ => {
+            let (a, b, c) = (low[(y, x - 1)], low[(y - 1, x)], low[(y - 1, x - 1)]);
+            if c >= a.max(b) { a.min(b) } else if c <= a.min(b) { a.max(b) } else { a + b - c }
+        },
+    }
+}
+
+/// Returns the quantisation step of a sample of the low-frequency image of a
+/// [`Pyramid`] with `order` levels, given its predicted value.
+///
+/// The step is proportional to the smallest visible change at the predicted
+/// brightness, so the image is effectively quantised in a perceptually
+/// uniform domain.
+///
+/// [`Pyramid`]: crate::Pyramid
+fn step(model: &impl PerceptualModel, order: usize, prediction: f32) -> f32 {
+    let luma = prediction * 0.5_f32.powi(order as i32);
+    let scale = order.saturating_sub(1);
+    LOW_STEP * model.tolerance(luma, scale, VHC::Vertical)
+}
+
+/// Quantise the low-frequency image of a [`Pyramid`] with `order` levels.
+///
+/// Samples are visited in raster order. Each is predicted from the already
+/// quantised samples above and to the left of it, and the prediction error is
+/// rounded to a whole number of steps. Returns the numbers of steps, which
+/// are typically small, and the quantised image, which is the same as
+/// [`low_from_digital()`] would return.
+///
+/// [`Pyramid`]: crate::Pyramid
+pub fn low_to_digital(
+    order: usize,
+    model: &impl PerceptualModel,
+    low: &Array<Grid, f32>,
+) -> (Array<Grid, i32>, Array<Grid, f32>) {
+    let mut decoded = low.clone();
+    let mut digital = Array::from_fn(low.size(), |_| 0);
+    low.size().each(|yx| {
+        let prediction = predict(&decoded, yx);
+        let step = step(model, order, prediction);
+        let q = ((low[yx] - prediction) / step).round();
+        let q = q.clamp(i32::MIN as f32, i32::MAX as f32) as i32;
+        digital[yx] = q;
+        decoded[yx] = prediction + q as f32 * step;
+    });
+    (digital, decoded)
+}
+
+/// The inverse of [`low_to_digital()`].
+pub fn low_from_digital(
+    order: usize,
+    model: &impl PerceptualModel,
+    digital: &Array<Grid, i32>,
+) -> Array<Grid, f32> {
+    let mut decoded = Array::from_fn(digital.size(), |_| 0.0);
+    digital.size().each(|yx| {
+        let prediction = predict(&decoded, yx);
+        decoded[yx] = prediction + digital[yx] as f32 * step(model, order, prediction);
+    });
+    decoded
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::quantize::{LumaModel};
+
+    #[test]
+    fn round_trip() {
+        let order = 3;
+        let model = LumaModel::default();
+        let low = Array::from_fn((5, 7), |(y, x)| (y * 7 + x) as f32 * 0.3 + if x > 3 { 4.0 } else { 0.0 });
+        let (digital, decoded) = low_to_digital(order, &model, &low);
+        let decoded2 = low_from_digital(order, &model, &digital);
+        (&decoded).zip(&decoded2).each(|(a, b)| assert_eq!(a, b));
+        low.size().each(|yx| {
+            let step = step(&model, order, predict(&decoded, yx));
+            assert!((decoded[yx] - low[yx]).abs() <= 0.5 * step + 1e-5);
+        });
+        // The image is planar except for an edge, so the predictions of
+        // samples that are not on the top row, left column or edge are within
+        // a couple of steps.
+        digital.size().each(|(y, x)| {
+            if y > 0 && x > 0 && x != 4 { assert!(digital[(y, x)].abs() <= 2); }
+        });
+    }
+}
@@ -4,88 +4,385 @@ use super::{Quad, Tree, VHC};
 use super::transform::{Haar};
 
 mod lattice;
-pub use lattice::{Lattice, D};
+pub use lattice::{Lattice, Voronoi, D, FCC, D4, BCC, E8, A2, Coset};
+
+mod glue;
+pub use glue::{H15, H15_DIMENSIONS, LAYOUT, Coordinate};
 
 mod bcc;
 pub use bcc::{ShiftedBCC, Symmetry, ALL_SYMMETRIES, Residual, ALL_RESIDUALS, Chain};
 
+mod perceptual;
+pub use perceptual::{PerceptualModel, LumaModel, ContrastSensitivity, Flat, CHROMA_LUMA, Chroma};
+
+mod low;
+pub use low::{low_to_digital, low_from_digital};
+
+#[cfg(test)]
+mod random;
+
 // ----------------------------------------------------------------------------
 
-fn tolerance(linear: f32) -> f32 {
-    let luma = if linear < 0.001 { 0.001 } else { linear };
-//    1.0 / 6.0
-//    luma / (4.0 * luma.sqrt())
-    luma / (3.0 * luma.cbrt())
-//    luma / (2.0 * luma.sqrt().sqrt())
-//    luma / 2.0
+/// Returns the tolerances of the [`VHC`] components of a wavelet coefficient
+/// at `level` in a [`Tree`] with `order` levels.
+fn tolerances(model: &impl PerceptualModel, order: usize, level: usize, low: f32) -> [f32; 3] {
+    let luma = low * 0.5_f32.powi((order - level) as i32);
+    let scale = order - 1 - level;
+    [VHC::Vertical, VHC::Horizontal, VHC::Cross].map(|vhc| model.tolerance(luma, scale, vhc))
+}
+
+/// The largest magnitude of a coefficient, after dividing by its tolerance,
+/// that [`to_digital()`] represents exactly. Larger coefficients are clamped,
+/// so that no [`Chain`] is longer than [`MAX_LENGTH`].
+///
+/// [`MAX_LENGTH`]: crate::encode::MAX_LENGTH
+const MAX_COEFFICIENT: f32 = 8192.0;
+
+/// Clamps `(v, h, c)` to [`MAX_COEFFICIENT`] and rounds it to the nearest
+/// [`ShiftedBCC`].
+fn quantize_clamped(v: f32, h: f32, c: f32) -> ShiftedBCC {
+    let clamp = |x: f32| x.clamp(-MAX_COEFFICIENT, MAX_COEFFICIENT);
+    ShiftedBCC::quantize(clamp(v), clamp(h), clamp(c)).0
+}
+
+/// Estimates the number of bits needed to code a digital [`Tree`].
+///
+/// This is used by [`to_digital_rd()`] to trade quality against size.
+pub trait Rate {
+    /// Returns the number of bits needed to code a [`Tree::Leaf`] at `level`
+    /// whose parent has payload `parent`, if any.
+    fn leaf(&self, level: usize, parent: Option<ShiftedBCC>) -> f32;
+
+    /// Returns the number of bits needed to code a [`Tree::Branch`] with
+    /// payload `bcc` at `level` whose parent has payload `parent`, if any,
+    /// excluding its children.
+    fn branch(&self, level: usize, parent: Option<ShiftedBCC>, bcc: ShiftedBCC) -> f32;
+}
+
+/// A [`Rate`] in which every [`Tree`] costs nothing.
+#[derive(Debug, Copy, Clone)]
+pub struct ZeroRate;
+
+impl Rate for ZeroRate {
+    fn leaf(&self, _: usize, _: Option<ShiftedBCC>) -> f32 { 0.0 }
+    fn branch(&self, _: usize, _: Option<ShiftedBCC>, _: ShiftedBCC) -> f32 { 0.0 }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A digital `(V, H, C)` triplet: the payload of a digital [`Tree`].
+pub trait Triplet: Copy {
+    /// Returns the coordinates of `self` in `[V, H, C]` order, in units of
+    /// the smallest visible difference.
+    fn vhc(self) -> [f32; 3];
+}
+
+impl Triplet for ShiftedBCC {
+    fn vhc(self) -> [f32; 3] { [self.v(), self.h(), self.c()] }
+}
+
+/// The lattice onto which [`quantize_tree()`] rounds wavelet coefficients.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Round each `(V, H, C)` triplet independently onto a [`ShiftedBCC`],
+    /// i.e. use the product lattice BCC^n, like [`to_digital()`].
+    #[default]
+    Product,
+
+    /// Round each family onto a shifted [`H15`], like
+    /// [`to_digital_family()`].
+    Family,
+}
+
+/// The factor by which [`to_digital_family()`] scales `H15`: `2^(6/15)`.
+/// `H15` is the union of `2^6` cosets of BCC^5.
+const FAMILY_SCALE: f32 = 1.319_508;
+
+/// The vector that [`to_digital_family()`] adds to each triplet of `H15`,
+/// before scaling.
+const FAMILY_SHIFT: [f32; 3] = ShiftedBCC::SHIFT;
+
+/// The coordinates of a triplet of [`H15`] chosen by [`to_digital_family()`],
+/// before shifting and scaling.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FamilyTriplet(pub [i32; 3]);
+
+impl Triplet for FamilyTriplet {
+    fn vhc(self) -> [f32; 3] {
+        [0, 1, 2].map(|i| FAMILY_SCALE * (self.0[i] as f32 + FAMILY_SHIFT[i]))
+    }
+}
+
+/// Returns the [`Coordinate`]s of a triplet of a family rounded by
+/// [`to_digital_family()`].
+fn to_family(analogue: [f32; 3], tolerances: [f32; 3]) -> [Coordinate; 3] {
+    [0, 1, 2].map(|i| Coordinate::Analogue(analogue[i] / tolerances[i] / FAMILY_SCALE - FAMILY_SHIFT[i]))
+}
+
+/// Returns the triplet of `h15` that begins with coordinate `i`.
+fn triplet_of(h15: H15, i: usize) -> FamilyTriplet {
+    let data = h15.coordinates();
+    FamilyTriplet([data[i], data[i + 1], data[i + 2]])
+}
+
+/// Returns the components of `payload` in `[V, H, C]` order.
+fn vhc_array(payload: &Array<VHC, f32>) -> [f32; 3] {
+    [VHC::Vertical, VHC::Horizontal, VHC::Cross].map(|vhc| payload.at(vhc))
+}
+
+// ----------------------------------------------------------------------------
+
+/// The result of `to_digital_inner()`.
+struct Digital<P> {
+    /// The digital [`Tree`].
+    tree: Tree<P>,
+
+    /// The L2 norm of the quantisation error (i.e. after dividing by
+    /// tolerance).
+    error_norm: f32,
+
+    /// The L2 norm of the analogue `Tree` (before dividing by tolerance).
+    leaf_norm: f32,
+
+    /// The estimated number of bits needed to code `tree`.
+    bits: f32,
+}
+
+/// The parameters of `to_digital_inner()` that are the same for every node.
+struct Params<'a, M, Q> {
+    order: usize,
+    model: &'a M,
+    lambda: f32,
+    rounding: &'a Q,
+}
+
+/// How `to_digital_inner()` chooses the payload of each [`Tree::Branch`],
+/// and estimates the number of bits needed to code it.
+trait Rounding {
+    /// The payload of a digital [`Tree::Branch`].
+    type Payload: Triplet;
+
+    /// Chooses the payload of a [`Tree::Branch`] at `level`, and possibly
+    /// the payloads of its children.
+    ///
+    /// - low - the low-frequency wavelet component of the branch.
+    /// - payload - the analogue payload of the branch.
+    /// - children - the children of the branch.
+    /// - chosen - the payload of the branch, if it was chosen with its parent.
+    fn round(
+        &self,
+        params: &Params<impl PerceptualModel, Self>,
+        low: f32,
+        payload: &Array<VHC, f32>,
+        children: &Quad<Tree<Array<VHC, f32>>>,
+        level: usize,
+        chosen: Option<Self::Payload>,
+    ) -> (Self::Payload, [Option<Self::Payload>; 4]) where Self: Sized;
+
+    /// Returns the number of bits needed to code a [`Tree::Leaf`] at `level`
+    /// whose parent has payload `parent`, if any.
+    fn leaf_bits(&self, level: usize, parent: Option<Self::Payload>) -> f32;
+
+    /// Returns the number of bits needed to code a [`Tree::Branch`] with
+    /// `payload` at `level` whose parent has payload `parent`, if any,
+    /// excluding its children.
+    fn branch_bits(&self, level: usize, parent: Option<Self::Payload>, payload: Self::Payload) -> f32;
+}
+
+/// The [`Rounding`] of [`to_digital_rd()`], which rounds each triplet onto a
+/// [`ShiftedBCC`] and uses a [`Rate`].
+struct ProductRounding<'a, R>(&'a R);
+
+impl<R: Rate> Rounding for ProductRounding<'_, R> {
+    type Payload = ShiftedBCC;
+
+    fn round(
+        &self,
+        params: &Params<impl PerceptualModel, Self>,
+        low: f32,
+        payload: &Array<VHC, f32>,
+        _: &Quad<Tree<Array<VHC, f32>>>,
+        level: usize,
+        _: Option<ShiftedBCC>,
+    ) -> (ShiftedBCC, [Option<ShiftedBCC>; 4]) {
+        let [tv, th, tc] = tolerances(params.model, params.order, level, low);
+        let [v, h, c] = vhc_array(payload);
+        (quantize_clamped(v / tv, h / th, c / tc), [None; 4])
+    }
+
+    fn leaf_bits(&self, level: usize, parent: Option<ShiftedBCC>) -> f32 {
+        self.0.leaf(level, parent)
+    }
+
+    fn branch_bits(&self, level: usize, parent: Option<ShiftedBCC>, bcc: ShiftedBCC) -> f32 {
+        self.0.branch(level, parent, bcc)
+    }
+}
+
+/// The [`Rounding`] of [`to_digital_family()`], which rounds each family
+/// onto [`H15`]. Every [`Tree`] costs nothing.
+struct FamilyRounding;
+
+impl Rounding for FamilyRounding {
+    type Payload = FamilyTriplet;
+
+    fn round(
+        &self,
+        params: &Params<impl PerceptualModel, Self>,
+        low: f32,
+        payload: &Array<VHC, f32>,
+        children: &Quad<Tree<Array<VHC, f32>>>,
+        level: usize,
+        chosen: Option<FamilyTriplet>,
+    ) -> (FamilyTriplet, [Option<FamilyTriplet>; 4]) {
+        let ts = tolerances(params.model, params.order, level, low);
+        let digital = chosen.unwrap_or_else(|| {
+            // The root has no parent, so only its own family constrains it.
+            let mut coordinates = [Coordinate::Free; H15_DIMENSIONS];
+            coordinates[..3].copy_from_slice(&to_family(vhc_array(payload), ts));
+            triplet_of(H15::quantize_partial(coordinates).0, 0)
+        });
+        let [v, h, c] = digital.vhc();
+        let mut coordinates = [Coordinate::Free; H15_DIMENSIONS];
+        coordinates[..3].copy_from_slice(&digital.0.map(Coordinate::Fixed));
+        let haar = Haar::new(low, ts[0] * v, ts[1] * h, ts[2] * c).transform();
+        let mut i = 3;
+        haar.zip(children.as_ref()).each(|(child_low, child)| {
+            coordinates[i..i + 3].copy_from_slice(&match child {
+                Tree::Branch(child) => to_family(vhc_array(&child.payload), tolerances(params.model, params.order, level + 1, child_low)),
+                Tree::Leaf => [Coordinate::Free; 3],
+            });
+            i += 3;
+        });
+        let h15 = H15::quantize_partial(coordinates).0;
+        (digital, [3, 6, 9, 12].map(|i| Some(triplet_of(h15, i))))
+    }
+
+    fn leaf_bits(&self, _: usize, _: Option<FamilyTriplet>) -> f32 { 0.0 }
+
+    fn branch_bits(&self, _: usize, _: Option<FamilyTriplet>, _: FamilyTriplet) -> f32 { 0.0 }
 }
 
-/// The recursive part of `to_digital()`.
+/// The recursive part of `to_digital_rd()` and `to_digital_family()`.
 ///
-/// Returns the digital [`Tree`], the L2 norm of the quantisation error (i.e.
-/// after dividing by sensitivity), and the L2 norm of `tree` (before dividing
-/// by sensitivity).
-fn to_digital_inner(low: f32, tree: &Tree<Array<VHC, f32>>, gain: f32) -> (Tree<ShiftedBCC>, f32, f32) {
+/// - parent - the payload of the parent of `tree`, if any.
+/// - chosen - the payload of `tree`, if it was chosen with its parent.
+fn to_digital_inner<Q: Rounding>(
+    params: &Params<impl PerceptualModel, Q>,
+    low: f32,
+    tree: &Tree<Array<VHC, f32>>,
+    level: usize,
+    parent: Option<Q::Payload>,
+    chosen: Option<Q::Payload>,
+) -> Digital<Q::Payload> {
     match tree {
         Tree::Branch(branch) => {
-            let tolerance = tolerance(low * gain);
-            let sensitivity = tolerance.recip();
-            let v = branch.payload.at(VHC::Vertical);
-            let h = branch.payload.at(VHC::Horizontal);
-            let c = branch.payload.at(VHC::Cross);
-            let mut leaf_norm = v * v + h * h + c * c;
-            let (bcc, mut branch_error_norm) = ShiftedBCC::quantize(
-                sensitivity * v,
-                sensitivity * h,
-                sensitivity * c,
-            );
-            let new_v = tolerance * bcc.v();
-            let new_h = tolerance * bcc.h();
-            let new_c = tolerance * bcc.c();
-            let haar = Haar::new(low, new_v, new_h, new_c).transform();
+            let [tv, th, tc] = tolerances(params.model, params.order, level, low);
+            let [v, h, c] = vhc_array(&branch.payload);
+            let (digital, children_chosen) = params.rounding.round(params, low, &branch.payload, &branch.children, level, chosen);
+            let [dv, dh, dc] = digital.vhc();
+            let mut branch_error_norm = (v / tv - dv).powi(2) + (h / th - dh).powi(2) + (c / tc - dc).powi(2);
+            let mut branch_bits = params.rounding.branch_bits(level, parent, digital);
+            let haar = Haar::new(low, tv * dv, th * dh, tc * dc).transform();
+            let mut children_norm = 0.0;
+            let mut k = 0;
             let children = Quad::new_view(((), ()), |buffer| {
                 haar.zip(branch.children.as_ref()).each(|(child_low, child)| {
-                    let (child, child_error_norm, child_leaf_norm) = to_digital_inner(child_low, child, gain * 2.0);
-                    branch_error_norm += child_error_norm;
-                    leaf_norm += child_leaf_norm;
-                    buffer.push(child);
+                    let child = to_digital_inner(params, child_low, child, level + 1, Some(digital), children_chosen[k]);
+                    k += 1;
+                    branch_error_norm += child.error_norm;
+                    children_norm += child.leaf_norm;
+                    branch_bits += child.bits;
+                    buffer.push(child.tree);
                 });
             });
-            let leaf_error_norm = leaf_norm * (sensitivity * sensitivity);
-            if leaf_error_norm < branch_error_norm {
+            // The children are measured using the mean sensitivity.
+            let sensitivity2 = (tv.powi(-2) + th.powi(-2) + tc.powi(-2)) / 3.0;
+            let leaf_error_norm = (v / tv).powi(2) + (h / th).powi(2) + (c / tc).powi(2) + children_norm * sensitivity2;
+            let leaf_norm = v * v + h * h + c * c + children_norm;
+            let leaf_bits = params.rounding.leaf_bits(level, parent);
+            if leaf_error_norm + params.lambda * leaf_bits < branch_error_norm + params.lambda * branch_bits {
                 // Quantise it to a leaf.
-                (Tree::Leaf, leaf_error_norm, leaf_norm)
+                Digital {tree: Tree::Leaf, error_norm: leaf_error_norm, leaf_norm, bits: leaf_bits}
             } else {
                 // Quantise it to a branch.
-                (Tree::branch(bcc, children), branch_error_norm, leaf_norm)
+                Digital {tree: Tree::branch(digital, children), error_norm: branch_error_norm, leaf_norm, bits: branch_bits}
             }
         },
-        Tree::Leaf => (Tree::Leaf, 0.0, 0.0),
+        Tree::Leaf => Digital {tree: Tree::Leaf, error_norm: 0.0, leaf_norm: 0.0, bits: 0.0},
     }
 }
 
-/// Convert an image tile from analogue to digitial form, after using a
-/// perceptual model to divide every value by the smallest visible difference.
-/// Blank subtrees are replaced with leaves.
+/// Convert an image tile from analogue to digitial form, after using `model`
+/// to divide every value by the smallest visible difference. Blank subtrees
+/// are replaced with leaves.
 ///
 /// - order - the number of generations of wavelets.
+/// - model - the perceptual model.
 /// - low - the low-frequency wavelet component of the tile.
 /// - tree - all other wavelet components of the tile.
-pub fn to_digital(order: usize, low: f32, tree: &Tree<Array<VHC, f32>>) -> Tree<ShiftedBCC> {
-    to_digital_inner(low, tree, 0.5_f32.powi(order as i32)).0
+pub fn to_digital(
+    order: usize,
+    model: &impl PerceptualModel,
+    low: f32,
+    tree: &Tree<Array<VHC, f32>>,
+) -> Tree<ShiftedBCC> {
+    to_digital_rd(order, model, low, tree, 0.0, &ZeroRate)
+}
+
+/// Like [`to_digital()`], but replaces a subtree with a leaf if that reduces
+/// `distortion + lambda * rate`, where `distortion` is the squared
+/// quantisation error, after dividing by the smallest visible difference, and
+/// `rate` is the number of bits estimated by `rate`.
+///
+/// If `lambda` is zero, this is the same as `to_digital()`.
+pub fn to_digital_rd(
+    order: usize,
+    model: &impl PerceptualModel,
+    low: f32,
+    tree: &Tree<Array<VHC, f32>>,
+    lambda: f32,
+    rate: &impl Rate,
+) -> Tree<ShiftedBCC> {
+    let params = Params {order, model, lambda, rounding: &ProductRounding(rate)};
+    to_digital_inner(&params, low, tree, 0, None, None).tree
+}
+
+/// Like [`to_digital()`], but rounds each parent triplet and its four
+/// children jointly onto a shifted [`H15`], scaled to have the same density
+/// as BCC^5, so that every family belongs to H15 ("Option 2" in `glue.md`).
+/// Families are rounded from the root down, each with its parent fixed, so
+/// the result is not always the nearest point of the lattice.
+///
+/// There is no way to code the result, so it is only useful for experiments.
+pub fn to_digital_family(
+    order: usize,
+    model: &impl PerceptualModel,
+    low: f32,
+    tree: &Tree<Array<VHC, f32>>,
+) -> Tree<FamilyTriplet> {
+    let params = Params {order, model, lambda: 0.0, rounding: &FamilyRounding};
+    to_digital_inner(&params, low, tree, 0, None, None).tree
 }
 
 /// The recursive part of `from_digital()`.
-pub fn from_digital_inner(low: f32, tree: &Tree<ShiftedBCC>, gain: f32) -> Tree<Array<VHC, f32>> {
+pub fn from_digital_inner(
+    order: usize,
+    model: &impl PerceptualModel,
+    low: f32,
+    tree: &Tree<impl Triplet>,
+    level: usize,
+) -> Tree<Array<VHC, f32>> {
     match tree {
         Tree::Branch(branch) => {
-            let tolerance = tolerance(low * gain);
-            let v = tolerance * branch.payload.v();
-            let h = tolerance * branch.payload.h();
-            let c = tolerance * branch.payload.c();
+            let [tv, th, tc] = tolerances(model, order, level, low);
+            let [dv, dh, dc] = branch.payload.vhc();
+            let v = tv * dv;
+            let h = th * dh;
+            let c = tc * dc;
             let haar = Haar::new(low, v, h, c).transform();
             let children = haar.zip(branch.children.as_ref()).map(
-                |(child_low, child)| from_digital_inner(child_low, child, gain * 2.0)
+                |(child_low, child)| from_digital_inner(order, model, child_low, child, level + 1)
             ).collect();
             Tree::branch(Array::new((), [v, h, c]), children)
         },
@@ -93,14 +390,49 @@ pub fn from_digital_inner(low: f32, tree: &Tree<ShiftedBCC>, gain: f32) -> Tree<
     }
 }
 
-/// Convert an image tile from digital to analogue form, then use a perceptual
-/// model to multiply every value by the smallest visible difference.
+/// Convert an image tile from digital to analogue form, then use `model` to
+/// multiply every value by the smallest visible difference.
 ///
 /// - order - the number of generations of wavelets.
+/// - model - the perceptual model.
 /// - low - the low-frequency wavelet component of the tile.
-/// - tree - all other wavelet components of the tile.
-pub fn from_digital(order: usize, low: f32, tree: &Tree<ShiftedBCC>) -> Tree<Array<VHC, f32>> {
-    from_digital_inner(low, tree, 0.5_f32.powi(order as i32))
+/// - tree - all other wavelet components of the tile, as [`ShiftedBCC`]s or
+///   [`FamilyTriplet`]s.
+pub fn from_digital(
+    order: usize,
+    model: &impl PerceptualModel,
+    low: f32,
+    tree: &Tree<impl Triplet>,
+) -> Tree<Array<VHC, f32>> {
+    from_digital_inner(order, model, low, tree, 0)
+}
+
+/// The part of `quantize_tree()` which is generic in the [`Rounding`].
+fn quantize_with(
+    params: &Params<impl PerceptualModel, impl Rounding>,
+    low: f32,
+    tree: &Tree<Array<VHC, f32>>,
+) -> (Tree<Array<VHC, f32>>, f32) {
+    let digital = to_digital_inner(params, low, tree, 0, None, None);
+    (from_digital(params.order, params.model, low, &digital.tree), digital.error_norm)
+}
+
+/// Rounds an image tile onto the lattice of `mode`, then converts it back
+/// using [`from_digital()`].
+///
+/// Returns the rounded tile and the squared quantisation error (after
+/// dividing by the smallest visible difference).
+pub fn quantize_tree(
+    order: usize,
+    model: &impl PerceptualModel,
+    mode: Mode,
+    low: f32,
+    tree: &Tree<Array<VHC, f32>>,
+) -> (Tree<Array<VHC, f32>>, f32) {
+    match mode {
+        Mode::Product => quantize_with(&Params {order, model, lambda: 0.0, rounding: &ProductRounding(&ZeroRate)}, low, tree),
+        Mode::Family => quantize_with(&Params {order, model, lambda: 0.0, rounding: &FamilyRounding}, low, tree),
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -109,6 +441,8 @@ pub fn from_digital(order: usize, low: f32, tree: &Tree<ShiftedBCC>) -> Tree<Arr
 mod tests {
     use super::*;
 
+    use random::{Random};
+
     #[test]
     fn round_trip() {
         let low = 0.5;
@@ -119,8 +453,130 @@ mod tests {
                 Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::Leaf),
             )),
         );
-        let analogue = from_digital(2, low, &digital);
-        let digital2 = to_digital(2, low, &analogue);
+        let analogue = from_digital(2, &LumaModel::default(), low, &digital);
+        let digital2 = to_digital(2, &LumaModel::default(), low, &analogue);
         assert_eq!(digital, digital2);
     }
+
+    /// A [`PerceptualModel`] that depends on everything except `luma`.
+    struct TestModel;
+
+    impl PerceptualModel for TestModel {
+        fn tolerance(&self, _: f32, scale: usize, vhc: VHC) -> f32 {
+            0.1 * (1 + scale) as f32 * (1 + vhc as usize) as f32
+        }
+    }
+
+    #[test]
+    fn anisotropic() {
+        let low = 0.5;
+        let digital = Tree::branch(
+            ShiftedBCC::new(2.0, -1.0, -0.5),
+            Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::branch(
+                ShiftedBCC::new(1.0, -2.0, 0.5),
+                Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::Leaf),
+            )),
+        );
+        let analogue = from_digital(2, &TestModel, low, &digital);
+        let Tree::Branch(branch) = &analogue else { panic!("Not a branch"); };
+        assert!((branch.payload.at(VHC::Vertical) - 0.4).abs() < 1e-6);
+        assert!((branch.payload.at(VHC::Horizontal) + 0.4).abs() < 1e-6);
+        assert!((branch.payload.at(VHC::Cross) + 0.3).abs() < 1e-6);
+        let digital2 = to_digital(2, &TestModel, low, &analogue);
+        assert_eq!(digital2, digital);
+    }
+
+    /// A [`Rate`] in which every node costs one bit, plus one bit per unit of
+    /// payload.
+    struct TestRate;
+
+    impl Rate for TestRate {
+        fn leaf(&self, _: usize, _: Option<ShiftedBCC>) -> f32 { 1.0 }
+        fn branch(&self, _: usize, _: Option<ShiftedBCC>, bcc: ShiftedBCC) -> f32 {
+            1.0 + bcc.v().abs() + bcc.h().abs() + bcc.c().abs()
+        }
+    }
+
+    fn num_branches_of<B>(tree: &Tree<B>) -> usize {
+        match tree {
+            Tree::Branch(branch) => {
+                let mut n = 1;
+                branch.children.as_ref().each(|child| { n += num_branches_of(child); });
+                n
+            },
+            Tree::Leaf => 0,
+        }
+    }
+
+    #[test]
+    fn rate_distortion() {
+        let low = 0.5;
+        let digital = Tree::branch(
+            ShiftedBCC::new(2.0, -1.0, -0.5),
+            Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::branch(
+                ShiftedBCC::new(1.0, -2.0, 0.5),
+                Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::Leaf),
+            )),
+        );
+        let analogue = from_digital(2, &LumaModel::default(), low, &digital);
+        let digital2 = to_digital_rd(2, &LumaModel::default(), low, &analogue, 0.0, &TestRate);
+        assert_eq!(digital2, digital);
+        let mut num_branches = usize::MAX;
+        for lambda in [0.01, 0.1, 1.0, 10.0, 100.0] {
+            let pruned = to_digital_rd(2, &LumaModel::default(), low, &analogue, lambda, &TestRate);
+            let n = num_branches_of(&pruned);
+            assert!(n <= num_branches);
+            num_branches = n;
+        }
+        assert_eq!(num_branches, 0);
+    }
+
+    /// Constructs a complete [`Tree`] with `depth` levels of pseudo-random
+    /// coefficients in the range `±scale`.
+    fn random_tree(depth: usize, scale: f32, random: &mut Random) -> Tree<Array<VHC, f32>> {
+        if depth == 0 { return Tree::Leaf; }
+        let payload = Array::new((), [(); 3].map(|()| (random.uniform() * 2.0 - 1.0) * scale));
+        let children = Quad::new_view(((), ()), |buffer| {
+            for _ in 0..4 { buffer.push(random_tree(depth - 1, scale, random)); }
+        });
+        Tree::branch(payload, children)
+    }
+
+    #[test]
+    fn family() {
+        let mut random = Random(1);
+        let mut errors = [0.0; 2];
+        for _ in 0..100 {
+            let tree = random_tree(3, 100.0, &mut random);
+            for (error, mode) in errors.iter_mut().zip([Mode::Product, Mode::Family]) {
+                let (rounded, error_norm) = quantize_tree(3, &TestModel, mode, 0.5, &tree);
+                assert_eq!(num_branches_of(&rounded), 21);
+                *error += error_norm;
+            }
+        }
+        let [product, family] = errors.map(|e| e / (100.0 * 63.0));
+        // BCC has a mean squared error of about `0.198` per dimension.
+        assert!((product - 0.198).abs() < 0.01);
+        assert!(family < product);
+    }
+
+    #[test]
+    fn family_point() {
+        // A family of `H15` in which the odd coordinates are `Vp`, `Va` and
+        // `Vd`, so that the triplets of `p`, `a` and `d` are not in BCC.
+        let data = [1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0];
+        assert!(H15::contains(&data));
+        let triplet = |i: usize| FamilyTriplet([data[i], data[i + 1], data[i + 2]]);
+        let child = |i| Tree::branch(triplet(i), Quad::new(Tree::Leaf, Tree::Leaf, Tree::Leaf, Tree::Leaf));
+        let digital = Tree::branch(triplet(0), Quad::new(child(3), child(6), child(9), child(12)));
+        let low = 0.5;
+        let analogue = from_digital(2, &TestModel, low, &digital);
+        // `to_digital_family()` finds the family exactly.
+        assert_eq!(to_digital_family(2, &TestModel, low, &analogue), digital);
+        let (_, family) = quantize_tree(2, &TestModel, Mode::Family, low, &analogue);
+        assert!(family < 1e-6, "{}", family);
+        // Rounding each triplet onto BCC cannot.
+        let (_, product) = quantize_tree(2, &TestModel, Mode::Product, low, &analogue);
+        assert!(product > 0.5, "{}", product);
+    }
 }
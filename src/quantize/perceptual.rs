@@ -0,0 +1,252 @@
+use crate::{Error, Result, VHC};
+
+/// A model of human vision that says how large a change in a wavelet
+/// coefficient must be before it is visible.
+pub trait PerceptualModel {
+    /// Returns the smallest visible change in a wavelet coefficient.
+    ///
+    /// - luma - the local brightness of the image, in linear units where `1.0`
+    ///   is white.
+    /// - scale - the number of generations of wavelets between the
+    ///   coefficient and the pixels, minus one. `0` means the finest detail.
+    /// - vhc - the orientation of the coefficient.
+    fn tolerance(&self, luma: f32, scale: usize, vhc: VHC) -> f32;
+}
+
+// ----------------------------------------------------------------------------
+
+/// The [`PerceptualModel`]s in which the tolerance depends only on the local
+/// brightness. These can be recorded in an FVQ file.
+///
+/// Below a brightness of `0.001`, the tolerance is constant.
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LumaModel {
+    /// The tolerance is `1/6`, whatever the brightness.
+    Constant = 0,
+
+    /// The tolerance is `luma / 2`.
+    Linear = 1,
+
+    /// The tolerance is `luma / (4 * luma.sqrt())`.
+    SquareRoot = 2,
+
+    /// The tolerance is `luma / (3 * luma.cbrt())`.
+    #[default]
+    CubeRoot = 3,
+
+    /// The tolerance is `luma / (2 * luma.sqrt().sqrt())`.
+    FourthRoot = 4,
+}
+
+impl LumaModel {
+    /// Returns the `LumaModel` whose discriminant is `x`.
+    pub fn from_u8(x: u8) -> Result<Self> {
+        Ok(match x {
+            0 => LumaModel::Constant,
+            1 => LumaModel::Linear,
+            2 => LumaModel::SquareRoot,
+            3 => LumaModel::CubeRoot,
+            4 => LumaModel::FourthRoot,
+            _ => Err(Error("Unknown perceptual model"))?,
+        })
+    }
+}
+
+impl std::str::FromStr for LumaModel {
+    type Err = Error;
+
+    /// Parses one of "constant", "linear", "sqrt", "cbrt" or "fourth-root".
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "constant" => LumaModel::Constant,
+            "linear" => LumaModel::Linear,
+            "sqrt" => LumaModel::SquareRoot,
+            "cbrt" => LumaModel::CubeRoot,
+            "fourth-root" => LumaModel::FourthRoot,
+            _ => Err(Error("Unknown perceptual model"))?,
+        })
+    }
+}
+
+impl PerceptualModel for LumaModel {
+    fn tolerance(&self, luma: f32, _: usize, _: VHC) -> f32 {
+        let luma = if luma < 0.001 { 0.001 } else { luma };
+        match self {
+            LumaModel::Constant => 1.0 / 6.0,
+            LumaModel::Linear => luma / 2.0,
+            LumaModel::SquareRoot => luma / (4.0 * luma.sqrt()),
+            LumaModel::CubeRoot => luma / (3.0 * luma.cbrt()),
+            LumaModel::FourthRoot => luma / (2.0 * luma.sqrt().sqrt()),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// The spatial frequency in cycles per degree at which human vision is most
+/// sensitive to contrast, according to [`csf()`].
+const PEAK_FREQUENCY: f32 = 8.0;
+
+/// The largest factor by which [`ContrastSensitivity`] increases a tolerance.
+const MAX_WEIGHT: f32 = 64.0;
+
+/// The sensitivity to diagonal detail relative to horizontal and vertical
+/// detail of the same frequency (the "oblique effect"). [`ContrastSensitivity`]
+/// models it by dividing the frequency of diagonal wavelets by this.
+const OBLIQUE_SENSITIVITY: f32 = 0.7;
+
+/// The contrast sensitivity function of Mannos and Sakrison, as a function of
+/// spatial frequency in cycles per degree. Its maximum is about `1.0`, at
+/// [`PEAK_FREQUENCY`].
+fn csf(frequency: f32) -> f32 {
+    let f = 0.114 * frequency;
+    2.6 * (0.0192 + f) * (-f.powf(1.1)).exp()
+}
+
+/// A [`PerceptualModel`] that multiplies the tolerance of `model` by the
+/// reciprocal of a contrast sensitivity function, so that fine and diagonal
+/// detail is quantised more coarsely.
+///
+/// Frequencies below [`PEAK_FREQUENCY`] are treated as if they were at the
+/// peak, so that the tolerance is never smaller than that of `model`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ContrastSensitivity<M> {
+    /// The model of sensitivity to brightness.
+    pub model: M,
+
+    /// The number of pixels per degree of visual angle, which depends on the
+    /// viewing distance. `0.0` disables the contrast sensitivity function.
+    pub pixels_per_degree: f32,
+}
+
+impl<M> ContrastSensitivity<M> {
+    /// Returns the spatial frequency in cycles per degree of a wavelet
+    /// coefficient.
+    pub fn frequency(&self, scale: usize, vhc: VHC) -> f32 {
+        // The finest wavelets have a period of two pixels.
+        let f = self.pixels_per_degree * 0.5_f32.powi(1 + scale as i32);
+        match vhc {
+            VHC::Vertical | VHC::Horizontal => f,
+            // Diagonal detail is finer by a factor of `√2`, and also less
+            // visible.
+            VHC::Cross => f * std::f32::consts::SQRT_2 / OBLIQUE_SENSITIVITY,
+        }
+    }
+
+    /// Returns the factor by which the tolerance of `model` is multiplied.
+    pub fn weight(&self, scale: usize, vhc: VHC) -> f32 {
+        let f = self.frequency(scale, vhc);
+        if f <= PEAK_FREQUENCY { return 1.0; }
+        (csf(PEAK_FREQUENCY) / csf(f)).min(MAX_WEIGHT)
+    }
+}
+
+impl<M: PerceptualModel> PerceptualModel for ContrastSensitivity<M> {
+    fn tolerance(&self, luma: f32, scale: usize, vhc: VHC) -> f32 {
+        self.model.tolerance(luma, scale, vhc) * self.weight(scale, vhc)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// A [`PerceptualModel`] in which the tolerance is the same everywhere.
+///
+/// This is suitable for channels that are not seen directly, such as alpha.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Flat(pub f32);
+
+impl PerceptualModel for Flat {
+    fn tolerance(&self, _: f32, _: usize, _: VHC) -> f32 { self.0 }
+}
+
+// ----------------------------------------------------------------------------
+
+/// The brightness at which [`Chroma`] evaluates its underlying model.
+pub const CHROMA_LUMA: f32 = 0.18;
+
+/// A [`PerceptualModel`] for a chroma channel.
+///
+/// The local brightness of a chroma channel is not known, so the tolerance is
+/// that of `model` at a brightness of [`CHROMA_LUMA`], multiplied by `factor`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Chroma<M> {
+    /// The model of the luma channel.
+    pub model: M,
+
+    /// The factor by which chroma is less visible than luma.
+    pub factor: f32,
+}
+
+impl<M: PerceptualModel> PerceptualModel for Chroma<M> {
+    fn tolerance(&self, _: f32, scale: usize, vhc: VHC) -> f32 {
+        self.model.tolerance(CHROMA_LUMA, scale, vhc) * self.factor
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_LUMA_MODELS: [LumaModel; 5] = [
+        LumaModel::Constant,
+        LumaModel::Linear,
+        LumaModel::SquareRoot,
+        LumaModel::CubeRoot,
+        LumaModel::FourthRoot,
+    ];
+
+    #[test]
+    fn from_u8() {
+        for model in ALL_LUMA_MODELS {
+            assert_eq!(LumaModel::from_u8(model as u8).unwrap(), model);
+        }
+        assert!(LumaModel::from_u8(ALL_LUMA_MODELS.len() as u8).is_err());
+        assert_eq!("cbrt".parse::<LumaModel>().unwrap(), LumaModel::CubeRoot);
+        assert!("gamma".parse::<LumaModel>().is_err());
+    }
+
+    #[test]
+    fn monotonic() {
+        for model in ALL_LUMA_MODELS {
+            let mut old = 0.0;
+            for luma in [0.0, 0.01, 0.1, 0.5, 1.0] {
+                let new = model.tolerance(luma, 0, VHC::Cross);
+                assert!(new >= old);
+                old = new;
+            }
+        }
+    }
+
+    #[test]
+    fn contrast_sensitivity() {
+        let off = ContrastSensitivity {model: LumaModel::CubeRoot, pixels_per_degree: 0.0};
+        for vhc in [VHC::Vertical, VHC::Horizontal, VHC::Cross] {
+            assert_eq!(off.tolerance(0.5, 0, vhc), LumaModel::CubeRoot.tolerance(0.5, 0, vhc));
+        }
+        let on = ContrastSensitivity {model: LumaModel::CubeRoot, pixels_per_degree: 60.0};
+        let mut old = f32::INFINITY;
+        for scale in 0..6 {
+            let v = on.weight(scale, VHC::Vertical);
+            assert!(v >= 1.0 && v <= old);
+            assert_eq!(v, on.weight(scale, VHC::Horizontal));
+            assert!(on.weight(scale, VHC::Cross) >= v);
+            old = v;
+        }
+        assert!(on.weight(0, VHC::Vertical) > 1.0);
+        assert_eq!(on.weight(5, VHC::Vertical), 1.0);
+        let far = ContrastSensitivity {model: LumaModel::CubeRoot, pixels_per_degree: 1e6};
+        assert_eq!(far.weight(0, VHC::Cross), MAX_WEIGHT);
+    }
+
+    #[test]
+    fn chroma() {
+        let chroma = Chroma {model: LumaModel::CubeRoot, factor: 2.0};
+        let expected = 2.0 * LumaModel::CubeRoot.tolerance(CHROMA_LUMA, 0, VHC::Cross);
+        for luma in [-0.5, 0.0, 0.5] {
+            assert_eq!(chroma.tolerance(luma, 0, VHC::Cross), expected);
+        }
+    }
+}
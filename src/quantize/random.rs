@@ -0,0 +1,11 @@
+/// A linear congruential pseudo-random number generator, for tests.
+#[derive(Debug, Copy, Clone)]
+pub struct Random(pub u32);
+
+impl Random {
+    /// Returns a pseudo-random number in the range `0.0` to `1.0`.
+    pub fn uniform(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -68,6 +68,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn haar() {
         let a: Array<Small, f32> = Array::new((), [1.0, 4.0, 2.0, 3.0]);
         let h: Haar = (&a).collect();
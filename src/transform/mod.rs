@@ -46,7 +46,9 @@ pub struct Position {
 }
 
 impl Position {
-    fn children(self) -> impl View<I=Small, T=Self> {
+    /// Returns the four `Position`s at the next level that are covered by
+    /// `self`.
+    pub fn children(self) -> impl View<I=Small, T=Self> {
         <(bool, bool)>::all(((), ())).map(move |bb| Position {
             level: self.level + 1,
             yx: (2 * self.yx.0 + bb.0 as usize, 2 * self.yx.1 + bb.1 as usize),
@@ -78,6 +80,16 @@ impl Pyramid {
         Self {low, highs: highs.into_iter().rev().collect()}
     }
 
+    /// Constructs a `Pyramid` whose high-frequency components are all zero.
+    pub fn from_low(order: usize, low: Array<Grid, f32>) -> Self {
+        let (height, width) = low.size();
+        let highs = (0..order).map(|level| {
+            let size = (height << level, width << level);
+            Array::from_fn((size, ()), |_| 0.0)
+        }).collect();
+        Self {low, highs}
+    }
+
     pub fn to_pixels(self, is_smooth: bool) -> Array<Grid, f32> {
         let mut low = self.low;
         let mut highs = self.highs.into_vec().into_iter().rev().collect::<Vec<Array<_, _>>>();
@@ -22,6 +22,7 @@ use super::{Grid, Haar};
 /// `atan(1/8)`.
 ///
 /// - IS_INVERSE - `true` for the inverse transform.
+#[allow(clippy::excessive_precision)]
 pub fn twiddle<const IS_INVERSE: bool>(hs: &mut[Haar]) {
     let n = hs.len();
     // a = 1.0 / 16.0
@@ -62,6 +63,7 @@ fn twiddle_columns<const IS_INVERSE: bool>(quads: Array<Grid, Haar>) -> Array<Gr
     }).nested_collect(height)
 }
 
+#[allow(clippy::let_and_return)]
 pub fn twiddle_grid<const IS_INVERSE: bool>(quads: Array<Grid, Haar>) -> Array<Grid, Haar> {
     let quads = twiddle_columns::<IS_INVERSE>(quads);
     let quads = twiddle_columns::<IS_INVERSE>(quads);
@@ -75,6 +77,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn round_trip() {
         let mut hs: [Haar; 3] = [
             Haar::new(1.25, 1.0, 2.5, 5.75),
@@ -94,6 +97,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn ramp() {
         let mut hs: [Haar; 8] = (0..8).map(|x| {
             let x = x as f32 * 2.0;